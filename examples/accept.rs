@@ -6,7 +6,7 @@ use nfq::message::Message;
 
 fn main() {
     let mut handle = Handle::new().ok().unwrap();
-    let _ = handle.bind(ProtocolFamily::INET).ok().unwrap();
+    handle.bind(ProtocolFamily::INET).ok().unwrap();
 
     let mut queue = handle.queue(0, move |message: &Message| {
       println!("Handling packet (ID: {})", message.header.id());
@@ -31,7 +31,7 @@ impl VerdictHandler for Counter {
                 println!("Accepting even packet: {}", c);
                 Verdict::Accept
             },
-            c @ _ => {
+            c => {
                 println!("Dropping odd packet: {}", c);
                 Verdict::Drop
             }
@@ -23,10 +23,9 @@ impl VerdictHandler for Decider {
     fn decide(&mut self, message: &Message) -> Verdict {
         println!("Handling packet (ID: {})", message.header.id());
         // Note that the queue was set and handle was started with `_sized`
-        match unsafe { message.ip_header() } {
-            Ok(ip_header) => println!("saddr: {}, daddr: {}", ip_header.saddr(), ip_header.daddr()),
-            Err(_) => ()
-        };
+        if let Ok(ip_header) = message.parse::<IPHeader>() {
+            println!("saddr: {}, daddr: {}", ip_header.saddr(), ip_header.daddr());
+        }
 
         Verdict::Accept
     }
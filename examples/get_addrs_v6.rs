@@ -0,0 +1,32 @@
+extern crate libnfqueue as nfq;
+
+use nfq::handle::{Handle, ProtocolFamily};
+use nfq::queue::{Verdict, VerdictHandler};
+use nfq::message::{Message, IPv6Header};
+
+fn main() {
+    let mut handle = Handle::new().ok().unwrap();
+    handle.bind(ProtocolFamily::INET6).ok().unwrap();
+
+    let mut queue = handle.queue(0, Decider).ok().unwrap();
+    queue.set_mode_sized::<IPv6Header>().ok().unwrap();
+
+    println!("Listening for packets...");
+    handle.start_sized::<IPv6Header>();
+
+    println!("...finished.");
+}
+
+struct Decider;
+
+impl VerdictHandler for Decider {
+    fn decide(&mut self, message: &Message) -> Verdict {
+        println!("Handling packet (ID: {})", message.header.id());
+        // Note that the queue was set and handle was started with `_sized`
+        if let Ok(ip_header) = message.parse::<IPv6Header>() {
+            println!("saddr: {}, daddr: {}", ip_header.saddr(), ip_header.daddr());
+        }
+
+        Verdict::Accept
+    }
+}
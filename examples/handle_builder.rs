@@ -0,0 +1,26 @@
+extern crate libnfqueue as nfq;
+
+use nfq::handle::{HandleBuilder, ProtocolFamily};
+use nfq::queue::{CopyMode, Verdict};
+use nfq::message::Message;
+
+fn main() {
+    let mut handle = HandleBuilder::new()
+        .protocol_family(ProtocolFamily::INET)
+        .protocol_family(ProtocolFamily::INET6)
+        .recv_buffer(8 * 1024 * 1024)
+        .read_buffer(4096)
+        .build()
+        .ok().unwrap();
+
+    let mut queue = handle.queue(0, move |message: &Message| {
+      println!("Handling packet (ID: {})", message.header.id());
+      Verdict::Accept
+    }).ok().unwrap();
+    queue.set_mode(CopyMode::Packet(1024)).ok().unwrap();
+
+    println!("Listening for packets...");
+    handle.start(4096);
+
+    println!("...finished.");
+}
@@ -0,0 +1,35 @@
+extern crate libnfqueue as nfq;
+
+use nfq::handle::{Handle, ProtocolFamily};
+use nfq::queue::{Verdict, VerdictHandler};
+use nfq::message::{Message, IPTcpHeader, L4};
+
+fn main() {
+    let mut handle = Handle::new().ok().unwrap();
+    handle.bind(ProtocolFamily::INET).ok().unwrap();
+
+    let mut queue = handle.queue(0, Decider).ok().unwrap();
+    // The TCP header is the largest of those parsed by `l4`
+    queue.set_mode_sized::<IPTcpHeader>().ok().unwrap();
+
+    println!("Listening for packets...");
+    handle.start_sized::<IPTcpHeader>();
+
+    println!("...finished.");
+}
+
+struct Decider;
+
+impl VerdictHandler for Decider {
+    fn decide(&mut self, message: &Message) -> Verdict {
+        match message.l4() {
+            Ok(L4::Tcp(tcp)) => println!("TCP {} -> {}", tcp.sport(), tcp.dport()),
+            Ok(L4::Udp(udp)) => println!("UDP {} -> {}", udp.sport(), udp.dport()),
+            Ok(L4::Icmp(icmp)) => println!("ICMP type {}", icmp.icmp_type()),
+            Ok(L4::Other(protocol)) => println!("Other protocol: {:?}", protocol),
+            Err(_) => ()
+        };
+
+        Verdict::Accept
+    }
+}
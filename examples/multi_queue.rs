@@ -0,0 +1,34 @@
+extern crate libnfqueue as nfq;
+
+use nfq::handle::{Handle, ProtocolFamily};
+use nfq::queue::{CopyMode, Verdict};
+use nfq::message::Message;
+
+fn main() {
+    let mut handle = Handle::new().ok().unwrap();
+    handle.bind(ProtocolFamily::INET).ok().unwrap();
+
+    let mut high = handle.queue(0, move |message: &Message| {
+      println!("High priority packet (ID: {})", message.header.id());
+      Verdict::Accept
+    }).ok().unwrap();
+    high.set_mode(CopyMode::Metadata).ok().unwrap();
+
+    let mut medium = handle.queue(1, move |message: &Message| {
+      println!("Medium priority packet (ID: {})", message.header.id());
+      Verdict::Accept
+    }).ok().unwrap();
+    medium.set_mode(CopyMode::Metadata).ok().unwrap();
+
+    let mut low = handle.queue(2, move |message: &Message| {
+      println!("Low priority packet (ID: {})", message.header.id());
+      Verdict::Accept
+    }).ok().unwrap();
+    low.set_mode(CopyMode::Metadata).ok().unwrap();
+
+    println!("Listening for packets on queues {}, {} and {}...",
+             high.number(), medium.number(), low.number());
+    handle.start(4096);
+
+    println!("...finished.");
+}
@@ -1,8 +1,7 @@
-extern crate netfilter_queue as nfq;
+extern crate libnfqueue as nfq;
 
-use std::ptr::null;
 use nfq::handle::{Handle, ProtocolFamily};
-use nfq::queue::{CopyMode, Verdict, PacketHandler, QueueHandle};
+use nfq::queue::{CopyMode, PacketHandler, QueueHandle};
 use nfq::message::Message;
 use nfq::error::Error;
 
@@ -11,7 +10,7 @@ fn main() {
     handle.bind(ProtocolFamily::INET).ok().unwrap();
 
     let mut queue = handle.queue(0, Decider).ok().unwrap();
-    let _ = queue.set_mode(CopyMode::Metadata).unwrap();
+    queue.set_mode(CopyMode::Metadata).unwrap();
 
     println!("Listening for packets...");
     handle.start(4096);
@@ -22,13 +21,9 @@ fn main() {
 struct Decider;
 
 impl PacketHandler for Decider {
-    #[allow(non_snake_case)]
-    fn handle(&mut self, hq: *mut QueueHandle, message: Result<&Message, &Error>) -> i32 {
-        match message {
-            Ok(m) => {
-                let _ = Verdict::set_verdict(hq, m.header.id(), Verdict::Accept, 0, null());
-            },
-            Err(_) => ()
+    fn handle(&mut self, hq: QueueHandle, message: Result<&Message, &Error>) -> i32 {
+        if let Ok(m) = message {
+            let _ = hq.accept(m.header.id());
         }
         0
     }
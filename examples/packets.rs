@@ -0,0 +1,31 @@
+extern crate libnfqueue as nfq;
+
+use nfq::handle::{Handle, ProtocolFamily};
+use nfq::queue::{CopyMode, Verdict};
+
+fn main() {
+    let mut handle = Handle::new().ok().unwrap();
+    handle.bind(ProtocolFamily::INET).ok().unwrap();
+
+    let mut queue = handle.event_queue(0).ok().unwrap();
+    queue.set_mode(CopyMode::Packet(4096)).ok().unwrap();
+
+    println!("Listening for packets...");
+    for event in handle.packets() {
+        match event {
+            Ok(event) => {
+                match event.payload {
+                    Ok(ref payload) => println!("Handling packet (ID: {}, {} bytes)", event.id, payload.len()),
+                    Err(ref e) => println!("Handling packet (ID: {}) without its payload: {}", event.id, e)
+                }
+                event.verdict.set(Verdict::Accept).ok().unwrap();
+            },
+            Err(e) => {
+                println!("Error: {}", e);
+                break;
+            }
+        }
+    }
+
+    println!("...finished.");
+}
@@ -0,0 +1,74 @@
+//! Driving a `Handle` from a tokio runtime
+//!
+//! Only available with the `tokio` feature.
+
+use errno::Errno;
+use std::future::{poll_fn, Future};
+use std::os::unix::io::RawFd;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+use error::*;
+use handle::Handle;
+use queue::PacketEvent;
+
+/// A `Handle` whose netlink socket is registered with the tokio reactor
+///
+/// Packets of the handle's `event_queue`s are awaited with `next_packet`,
+/// while packets of queues with callbacks are dispatched to their handlers along the way.
+/// Like `Handle`, this is not `Send`, so it must be driven from a `LocalSet` or a
+/// current-thread runtime.
+pub struct AsyncHandle {
+    // Declared first so that it is deregistered before the handle closes the socket
+    fd: AsyncFd<RawFd>,
+    handle: Handle
+}
+
+impl AsyncHandle {
+    /// Register the socket of `handle` with the current tokio runtime
+    ///
+    /// This must be called from within a runtime with IO enabled.
+    pub fn new(handle: Handle) -> Result<AsyncHandle, Error> {
+        let fd = handle.fd();
+        match AsyncFd::new(fd) {
+            Ok(fd) => Ok(AsyncHandle { fd: fd, handle: handle }),
+            Err(e) => Err(error_with_errno(Reason::RegisterAsync, "Failed to register socket",
+                                           None, Errno(e.raw_os_error().unwrap_or(0))))
+        }
+    }
+
+    /// Get the wrapped `Handle`, e.g. to create more queues
+    pub fn get_mut(&mut self) -> &mut Handle {
+        &mut self.handle
+    }
+
+    /// Wait for the next packet of the handle's `event_queue`s
+    ///
+    /// This awaits readability of the socket, then dispatches pending messages with `process_pending`.
+    pub fn next_packet(&mut self) -> impl Future<Output = Result<PacketEvent, Error>> + '_ {
+        poll_fn(move |cx| self.poll_next_packet(cx))
+    }
+
+    /// Poll for the next packet of the handle's `event_queue`s
+    pub fn poll_next_packet(&mut self, cx: &mut Context<'_>) -> Poll<Result<PacketEvent, Error>> {
+        loop {
+            if let Some(event) = self.handle.pop_event() {
+                return Poll::Ready(event);
+            }
+
+            let mut guard = match self.fd.poll_read_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => {
+                    return Poll::Ready(Err(error_with_errno(Reason::Recv, "Failed to poll socket",
+                                                            None, Errno(e.raw_os_error().unwrap_or(0)))))
+                },
+                Poll::Pending => return Poll::Pending
+            };
+
+            // `process_pending` only returns successfully once the socket would block
+            match self.handle.process_pending() {
+                Ok(_) => guard.clear_ready(),
+                Err(e) => return Poll::Ready(Err(e))
+            }
+        }
+    }
+}
@@ -1,41 +1,101 @@
 #![allow(missing_docs)]
 
-use libc::c_int;
+use errno::{errno, Errno};
+use libc::{c_int, ENOPROTOOPT, EPROTONOSUPPORT};
 use std::error::Error as Base;
 use std::fmt;
-use ffi::nfq_errno;
 
 #[derive(Debug)]
 pub enum Reason {
     OpenHandle,
+    /// The `nfnetlink_queue` kernel module is not loaded
+    ModuleNotLoaded,
+    /// The process lacks `CAP_NET_ADMIN`
+    PermissionDenied,
+    /// The network namespace could not be entered or restored
+    SetNetns,
     Bind,
     Unbind,
     CreateQueue,
+    /// The queue number is bound by another process
+    ///
+    /// This is only reported once the handle has bound a protocol family.
+    QueueBusy,
+    DestroyQueue,
+    /// The queue was destroyed before a verdict or setting could be applied through a `QueueHandle`
+    QueueDestroyed,
     SetQueueMode,
     SetQueueMaxlen,
+    SetQueueFlags,
     SetVerdict,
     GetHeader,
     GetPayload,
+    GetTimestamp,
+    FormatXml,
+    OpenInterfaceCache,
+    QueryInterfaces,
+    Recv,
+    Poll,
+    /// A received message could not be handled by the attached queues
+    HandlePacket,
+    SetSocketOption,
+    RegisterAsync,
+    ReadKernelStats,
+    /// The packet's headers are malformed, or not of the IP version expected
+    InvalidPacket,
+    /// Fewer bytes of the packet were copied to userspace than are needed
+    PayloadTooShort { got: usize, need: usize },
 }
 
 pub struct Error {
     reason: Reason,
     description: String,
-    cause: Option<Box<Base>>,
+    errno: Option<Errno>,
+    cause: Option<Box<dyn Base + Send + Sync>>,
 }
 
 impl fmt::Debug for Error {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let msg = format!("{:?}: {:?} (cause: {:?})",
-                          self.reason, self.description, self.cause);
-        formatter.write_str(msg.as_ref())
+        formatter.debug_struct("Error")
+            .field("reason", &self.reason)
+            .field("description", &self.description)
+            .field("errno", &self.errno.map(|e| e.0))
+            .field("cause", &self.cause)
+            .finish()
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let msg = format!("{:?} ({:?})", self.reason, self.description);
-        formatter.write_str(msg.as_ref())
+        write!(formatter, "{} ({:?})", self.description, self.reason)?;
+        if let Some(e) = self.errno {
+            write!(formatter, ": {} (errno {})", e, e.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// The reason for the error
+    pub fn reason(&self) -> &Reason {
+        &self.reason
+    }
+
+    /// The number of payload bytes available and needed, if the payload was too short
+    ///
+    /// When this is `Some`, the `Queue`'s `CopyMode` range is likely too small.
+    pub fn payload_sizes(&self) -> Option<(usize, usize)> {
+        match self.reason {
+            Reason::PayloadTooShort { got, need } => Some((got, need)),
+            _ => None
+        }
+    }
+
+    /// The OS error code (`errno`) recorded when the error occurred, if any
+    ///
+    /// For example, this distinguishes `ENOBUFS` from `EPERM` when receiving or configuring the socket.
+    pub fn os_error(&self) -> Option<i32> {
+        self.errno.map(|e| e.0)
     }
 }
 
@@ -43,20 +103,46 @@ impl Base for Error {
     fn description(&self) -> &str {
         self.description.as_ref()
     }
-    fn cause(&self) -> Option<&Base> {
-        self.cause.as_ref().map(|c| &**c)
+    fn source(&self) -> Option<&(dyn Base + 'static)> {
+        self.cause.as_ref().map(|c| &**c as &(dyn Base + 'static))
     }
 }
 
 pub fn error(reason: Reason, msg: &str, res: Option<c_int>) -> Error {
-    let errno = nfq_errno;
+    error_with_errno(reason, msg, res, errno())
+}
+
+// For failure sites that capture `errno` themselves, before it can be clobbered
+pub fn error_with_errno(reason: Reason, msg: &str, res: Option<c_int>, errno: Errno) -> Error {
     let desc = match res {
-        Some(r) => format!("{} (errno: {}, res: {})", msg, errno, r),
-        None => format!("{}, (errno: {})", msg, errno)
+        Some(r) => format!("{} (res: {})", msg, r),
+        None => msg.to_string()
     };
     Error {
         reason: reason,
         description: desc,
+        errno: if errno.0 != 0 { Some(errno) } else { None },
+        cause: None,
+    }
+}
+
+// For requests to the `nfnetlink_queue` subsystem, which the kernel refuses if the module is not loaded
+pub fn subsystem_error(reason: Reason, msg: &str, res: Option<c_int>, errno: Errno) -> Error {
+    match errno.0 {
+        e if e == EPROTONOSUPPORT || e == ENOPROTOOPT => {
+            error_with_errno(Reason::ModuleNotLoaded,
+                             &format!("{}: is nfnetlink_queue loaded? Try `modprobe nfnetlink_queue`", msg),
+                             res, errno)
+        },
+        _ => error_with_errno(reason, msg, res, errno)
+    }
+}
+
+pub fn payload_too_short(got: usize, need: usize) -> Error {
+    Error {
+        reason: Reason::PayloadTooShort { got: got, need: need },
+        description: format!("Payload too short (got: {}, need: {})", got, need),
+        errno: None,
         cause: None,
     }
 }
@@ -1,30 +1,117 @@
 #![allow(non_camel_case_types)]
 
 use libc::*;
-use num::traits::PrimInt;
 
 pub const NF_DROP: u32 = 0;
 pub const NF_ACCEPT: u32 = 1;
 pub const NF_STOLEN: u32 = 2;
 pub const NF_QUEUE: u32 = 3;
+// The queue number of an `NF_QUEUE` verdict is held in the high 16 bits
+pub const NF_VERDICT_QBITS: u32 = 16;
 pub const NF_REPEAT: u32 = 4;
 pub const NF_STOP: u32 = 5;
 
+pub const NFQA_MARK: u16 = 3;
+pub const NFQA_TIMESTAMP: u16 = 4;
+pub const NFQA_IFINDEX_INDEV: u16 = 5;
+pub const NFQA_IFINDEX_OUTDEV: u16 = 6;
+pub const NFQA_IFINDEX_PHYSINDEV: u16 = 7;
+pub const NFQA_IFINDEX_PHYSOUTDEV: u16 = 8;
+pub const NFQA_HWADDR: u16 = 9;
+pub const NFQA_PAYLOAD: u16 = 10;
+pub const NFQA_CT: u16 = 11;
+pub const NFQA_CT_INFO: u16 = 12;
+pub const NFQA_CAP_LEN: u16 = 13;
+pub const NFQA_UID: u16 = 16;
+pub const NFQA_GID: u16 = 17;
+pub const NFQA_VLAN: u16 = 19;
+
+pub const NFQA_SKB_CSUMNOTREADY: u32 = 1 << 0;
+pub const NFQA_SKB_GSO: u32 = 1 << 1;
+pub const NFQA_SKB_CSUM_NOTVERIFIED: u32 = 1 << 2;
+
+pub const NFPROTO_ARP: u16 = 3;
+pub const NFPROTO_NETDEV: u16 = 5;
+pub const NFPROTO_BRIDGE: u16 = 7;
+
+pub const MSG_DONTWAIT: c_int = 0x40;
+pub const SO_RCVBUFFORCE: c_int = 33;
+pub const SO_PROTOCOL: c_int = 38;
+pub const SOL_NETLINK: c_int = 270;
+pub const NETLINK_NETFILTER: c_int = 12;
+pub const NETLINK_NO_ENOBUFS: c_int = 5;
+pub const MSG_WAITFORONE: c_int = 0x10000;
+pub const CLONE_NEWNET: c_int = 0x40000000;
+
 #[repr(C)]
-pub struct nfq_handle;
+pub struct nfq_handle {
+    _private: [u8; 0]
+}
 
 #[repr(C)]
 /// The handle into NFQueue
-pub struct nfq_q_handle;
+pub struct nfq_q_handle {
+    _private: [u8; 0]
+}
+
+#[repr(C)]
+/// The netfilter netlink header of a message
+pub struct nfgenmsg {
+    _private: [u8; 0]
+}
+
+#[repr(C)]
+/// The packet data of a message, as passed to `nfq_get_*`
+pub struct nfq_data {
+    _private: [u8; 0]
+}
+
+#[repr(C)]
+pub struct nfattr {
+    pub nfa_len: uint16_t,
+    pub nfa_type: uint16_t
+}
 
+// The socket structures of glibc on Linux, for `recvmmsg`
 #[repr(C)]
-pub struct nfgenmsg;
+pub struct iovec {
+    pub iov_base: *mut c_void,
+    pub iov_len: size_t
+}
 
 #[repr(C)]
-pub struct nfq_data;
+pub struct msghdr {
+    pub msg_name: *mut c_void,
+    pub msg_namelen: socklen_t,
+    pub msg_iov: *mut iovec,
+    pub msg_iovlen: size_t,
+    pub msg_control: *mut c_void,
+    pub msg_controllen: size_t,
+    pub msg_flags: c_int
+}
+
+#[repr(C)]
+pub struct mmsghdr {
+    pub msg_hdr: msghdr,
+    pub msg_len: c_uint
+}
 
 #[repr(C)]
-#[packed]
+pub struct pollfd {
+    pub fd: c_int,
+    pub events: c_short,
+    pub revents: c_short
+}
+
+pub const POLLIN: c_short = 0x1;
+
+#[repr(C)]
+pub struct nlif_handle {
+    _private: [u8; 0]
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
 /// The NFQueue specific packet data
 pub struct nfqnl_msg_packet_hdr {
     /// The packet id
@@ -43,12 +130,29 @@ impl nfqnl_msg_packet_hdr {
     ///
     /// This id should be passed to `set_verdict` to set the destiny of the packet.
     pub fn id(&self) -> u32 { u32::from_be(self.packet_id) }
+
+    /// Extract the HW protocol of the packet in local endianness
+    ///
+    /// This is the ethertype of the L3 payload, e.g. `0x0800` for IPv4 or `0x86dd` for IPv6.
+    pub fn hw_protocol(&self) -> u16 { u16::from_be(self.hw_protocol) }
+
+    /// Get the netfilter hook the packet was queued from
+    ///
+    /// For the IP families, this is one of `NF_INET_PRE_ROUTING` (0), `NF_INET_LOCAL_IN` (1),
+    /// `NF_INET_FORWARD` (2), `NF_INET_LOCAL_OUT` (3) or `NF_INET_POST_ROUTING` (4).
+    pub fn hook(&self) -> u8 { self.hook }
 }
 
-#[link(name="netfilter_queue")]
-extern {
-    pub static nfq_errno: c_int;
+#[repr(C)]
+pub struct nfqnl_msg_packet_hw {
+    /// Length of the address (network order)
+    pub hw_addrlen: uint16_t,
+    pub _pad: uint16_t,
+    pub hw_addr: [uint8_t; 8]
+}
 
+#[link(name="netfilter_queue")]
+extern "C" {
     // Library setup
     pub fn nfq_open() -> *mut nfq_handle;
     pub fn nfq_close(handle: *mut nfq_handle) -> c_int;
@@ -69,6 +173,9 @@ extern {
                         range: uint32_t) -> c_int;
     pub fn nfq_set_queue_maxlen(handle: *mut nfq_q_handle,
                                 queuelen: uint32_t) -> c_int;
+    pub fn nfq_set_queue_flags(handle: *mut nfq_q_handle,
+                               mask: uint32_t,
+                               flags: uint32_t) -> c_int;
 
     // Iterating through a queue
     pub fn nfq_fd(handle: *mut nfq_handle) -> c_int;
@@ -82,8 +189,59 @@ extern {
                            verdict: uint32_t,
                            data_len: uint32_t,
                            buf: *const c_uchar) -> c_int;
+    pub fn nfq_set_verdict_batch(handle: *mut nfq_q_handle,
+                                 id: uint32_t,
+                                 verdict: uint32_t) -> c_int;
+    pub fn nfq_set_verdict2(handle: *mut nfq_q_handle,
+                            id: uint32_t,
+                            verdict: uint32_t,
+                            mark: uint32_t,
+                            data_len: uint32_t,
+                            buf: *const c_uchar) -> c_int;
 
     // Parsing the message
     pub fn nfq_get_msg_packet_hdr(nfad: *mut nfq_data) -> *const nfqnl_msg_packet_hdr;
     pub fn nfq_get_payload  (nfad: *mut nfq_data, data: *mut *mut c_uchar) -> c_int;
+    pub fn nfq_get_nfmark(nfad: *mut nfq_data) -> uint32_t;
+    pub fn nfq_get_packet_hw(nfad: *mut nfq_data) -> *const nfqnl_msg_packet_hw;
+    pub fn nfq_get_uid(nfad: *mut nfq_data, uid: *mut uint32_t) -> c_int;
+    pub fn nfq_get_gid(nfad: *mut nfq_data, gid: *mut uint32_t) -> c_int;
+    pub fn nfq_get_secctx(nfad: *mut nfq_data, secdata: *mut *mut c_uchar) -> c_int;
+    pub fn nfq_get_skbinfo(nfad: *mut nfq_data) -> uint32_t;
+    pub fn nfq_get_timestamp(nfad: *mut nfq_data, tv: *mut timeval) -> c_int;
+    pub fn nfq_get_indev(nfad: *mut nfq_data) -> uint32_t;
+    pub fn nfq_get_physindev(nfad: *mut nfq_data) -> uint32_t;
+    pub fn nfq_get_outdev(nfad: *mut nfq_data) -> uint32_t;
+    pub fn nfq_get_physoutdev(nfad: *mut nfq_data) -> uint32_t;
+    pub fn nfq_get_indev_name(nlif_handle: *mut nlif_handle,
+                              nfad: *mut nfq_data,
+                              name: *mut c_char) -> c_int;
+    pub fn nfq_get_physindev_name(nlif_handle: *mut nlif_handle,
+                                  nfad: *mut nfq_data,
+                                  name: *mut c_char) -> c_int;
+    pub fn nfq_get_outdev_name(nlif_handle: *mut nlif_handle,
+                               nfad: *mut nfq_data,
+                               name: *mut c_char) -> c_int;
+    pub fn nfq_get_physoutdev_name(nlif_handle: *mut nlif_handle,
+                                   nfad: *mut nfq_data,
+                                   name: *mut c_char) -> c_int;
+
+    // Printing the message
+    pub fn nfq_snprintf_xml(buf: *mut c_char, rem: size_t, tb: *mut nfq_data, flags: c_int) -> c_int;
+}
+
+#[link(name="nfnetlink")]
+extern "C" {
+    // Interface resolution
+    pub fn nlif_open() -> *mut nlif_handle;
+    pub fn nlif_close(handle: *mut nlif_handle);
+    pub fn nlif_query(handle: *mut nlif_handle) -> c_int;
+}
+
+extern "C" {
+    pub fn recvmmsg(sockfd: c_int, msgvec: *mut mmsghdr, vlen: c_uint, flags: c_int, timeout: *mut timespec) -> c_int;
+    pub fn setns(fd: c_int, nstype: c_int) -> c_int;
+    pub fn poll(fds: *mut pollfd, nfds: c_ulong, timeout: c_int) -> c_int;
+    pub fn getsockopt(sockfd: c_int, level: c_int, optname: c_int,
+                      optval: *mut c_void, optlen: *mut socklen_t) -> c_int;
 }
@@ -0,0 +1,66 @@
+//! Fluent configuration for NFQueue handles.
+use error::*;
+use super::{Handle, ProtocolFamily};
+
+/// A builder to open and configure a `Handle` in one step
+///
+/// Settings are collected, then applied by `build`.
+/// If any setting fails to apply, the handle is closed before the error is returned.
+pub struct HandleBuilder {
+    families: Vec<ProtocolFamily>,
+    recv_buffer_size: Option<usize>,
+    read_buffer_size: Option<usize>
+}
+
+impl HandleBuilder {
+    /// Create a builder which binds no families and applies no settings
+    pub fn new() -> HandleBuilder {
+        HandleBuilder {
+            families: Vec::new(),
+            recv_buffer_size: None,
+            read_buffer_size: None
+        }
+    }
+
+    /// Bind the handle to a `ProtocolFamily`
+    ///
+    /// This may be called more than once to bind several families.
+    pub fn protocol_family(mut self, proto: ProtocolFamily) -> HandleBuilder {
+        self.families.push(proto);
+        self
+    }
+
+    /// Set the size of the netlink socket's receive buffer, as with `set_recv_buffer_size`
+    pub fn recv_buffer(mut self, bytes: usize) -> HandleBuilder {
+        self.recv_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Set the size of the userspace buffer that packets are read into, as with `set_read_buffer_size`
+    pub fn read_buffer(mut self, bytes: usize) -> HandleBuilder {
+        self.read_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Open the handle and apply the collected settings
+    pub fn build(self) -> Result<Handle, Error> {
+        // On error, dropping `handle` closes it
+        let mut handle = Handle::new()?;
+        for proto in self.families {
+            handle.bind(proto)?;
+        }
+        if let Some(bytes) = self.recv_buffer_size {
+            handle.set_recv_buffer_size(bytes)?;
+        }
+        if let Some(bytes) = self.read_buffer_size {
+            handle.set_read_buffer_size(bytes);
+        }
+        Ok(handle)
+    }
+}
+
+impl Default for HandleBuilder {
+    fn default() -> HandleBuilder {
+        HandleBuilder::new()
+    }
+}
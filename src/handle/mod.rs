@@ -0,0 +1,790 @@
+//! The handle into NFQueue for library setup.
+//!
+//! Analagous to <http://netfilter.org/projects/libnetfilter_queue/doxygen/group__LibrarySetup.html>
+mod builder;
+
+use errno::{errno, Errno};
+use libc::*;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::mem;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::ptr::null_mut;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use error::*;
+use queue::{Queue, QueueHandle, PacketHandler, FnHandler, HandlerResult, PacketEvent, EventHandler, EventBuffer,
+            KernelQueueStats, kernel_stats};
+use message::{Message, Payload};
+use lock::NFQ_LOCK as LOCK;
+pub use self::builder::HandleBuilder;
+
+use ffi::*;
+
+// Large enough for any netlink message carrying a full 64KB packet
+const DEFAULT_READ_BUFFER_SIZE: usize = 65536 + 4096;
+
+// The network namespace of the calling thread
+const CURRENT_NETNS: &str = "/proc/thread-self/ns/net";
+
+// How often `start_until` checks its stop flag while idle
+const STOP_POLL_INTERVAL_MS: u64 = 100;
+
+/// Protocol Family
+///
+/// These are the netfilter protocol families which can queue packets.
+/// The IP families share their values with the corresponding address families.
+pub enum ProtocolFamily {
+    /// IPv4 Address Family
+    INET = AF_INET as isize,
+    /// IPv6 Address Family
+    INET6 = AF_INET6 as isize,
+    /// ARP, queued by `arptables`
+    ARP = NFPROTO_ARP as isize,
+    /// Bridged frames, queued by `ebtables`
+    BRIDGE = NFPROTO_BRIDGE as isize,
+    /// Ingress and egress of network devices
+    NETDEV = NFPROTO_NETDEV as isize
+}
+
+// Called with each error reported by the socket while dispatching packets
+type PacketErrorCallback = Box<dyn FnMut(&Error)>;
+
+/// A handle into NFQueue
+///
+/// This is needed for library setup.
+/// The library's handle is shared with the queues created on it, and so is only closed once
+/// the `Handle`, its queues and their `QueueHandle`s have all been dropped.
+pub struct Handle {
+    state: Arc<HandleState>,
+    read_buffer_size: usize,
+    // Kept between calls to `process_pending`, which an event loop calls for every wakeup
+    read_buffer: Vec<u8>,
+    overflow_callback: Option<Box<dyn FnMut()>>,
+    packet_error_callback: Option<PacketErrorCallback>,
+    events: EventBuffer,
+    // Set once a protocol family is bound, which proves the process has `CAP_NET_ADMIN`
+    bound: bool,
+    // The descriptor passed to `from_raw_fd`, if the handle owns it
+    adopted_fd: Option<RawFd>
+}
+
+// The library's handle, shared with the queues created on it, which lives as long as any of them
+pub(crate) struct HandleState {
+    pub ptr: *mut nfq_handle,
+    // Cleared when the socket is handed off with `into_raw_fd`
+    close_on_drop: AtomicBool
+}
+
+// The library's handle is only used under the library lock
+unsafe impl Send for HandleState {}
+unsafe impl Sync for HandleState {}
+
+impl Drop for HandleState {
+    fn drop(&mut self) {
+        if !self.close_on_drop.load(Ordering::Relaxed) {
+            return;
+        }
+        // The last reference may be dropped on any thread, where panicking could abort,
+        // so failures are only logged
+        let _lock = LOCK.lock().unwrap();
+        let ret = unsafe { nfq_close(self.ptr) };
+        if ret != 0 {
+            error!("Failed to close NFQHandle");
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Some(fd) = self.adopted_fd {
+            unsafe { close(fd) };
+        }
+    }
+}
+
+impl Handle {
+    /// Open a new handle to NFQueue
+    ///
+    /// This tells the kernel that userspace queuing will be handled for the selected protocol.
+    /// Opening the socket needs no privileges, nor the `nfnetlink_queue` kernel module:
+    /// a process lacking `CAP_NET_ADMIN` is refused with `Reason::PermissionDenied`, and a missing
+    /// module is reported with `Reason::ModuleNotLoaded`, only once it binds a family or creates a queue.
+    pub fn new() -> Result<Handle, Error> {
+        let _lock = LOCK.lock().unwrap();
+
+        let ptr = unsafe { nfq_open() };
+        if ptr.is_null() {
+            Err(error(Reason::OpenHandle, "Failed to allocate handle", None))
+        } else {
+            Ok(Handle {
+                state: Arc::new(HandleState { ptr: ptr, close_on_drop: AtomicBool::new(true) }),
+                read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+                read_buffer: Vec::new(),
+                overflow_callback: None,
+                packet_error_callback: None,
+                events: Rc::new(RefCell::new(VecDeque::new())),
+                bound: false,
+                adopted_fd: None
+            })
+        }
+    }
+
+    /// Open a new handle to NFQueue in the network namespace `netns`
+    ///
+    /// `netns` is a file descriptor of a namespace, e.g. opened from `/var/run/netns/<name>`.
+    /// The calling thread enters the namespace to open the handle, then returns to its own;
+    /// the handle's socket stays in `netns`, so its queues receive that namespace's packets.
+    /// This requires `CAP_SYS_ADMIN`.
+    pub fn open_in_netns(netns: RawFd) -> Result<Handle, Error> {
+        let current = File::open(CURRENT_NETNS).map_err(|e| {
+            error_with_errno(Reason::SetNetns, "Failed to open current network namespace", None,
+                             Errno(e.raw_os_error().unwrap_or(0)))
+        })?;
+
+        let res = unsafe { setns(netns, CLONE_NEWNET) };
+        if res < 0 {
+            return Err(error(Reason::SetNetns, "Failed to enter network namespace", Some(res)));
+        }
+
+        let handle = Handle::new();
+
+        let res = unsafe { setns(current.as_raw_fd(), CLONE_NEWNET) };
+        if res < 0 {
+            // On error, dropping `handle` closes it
+            return Err(error(Reason::SetNetns, "Failed to restore network namespace", Some(res)));
+        }
+        handle
+    }
+
+    /// Open a new handle to NFQueue on an existing netlink socket
+    ///
+    /// This is the inverse of `into_raw_fd`, for sockets managed by other netlink plumbing.
+    /// `fd` must be a `NETLINK_NETFILTER` socket, else this fails with `Reason::OpenHandle`
+    /// before anything is opened.
+    ///
+    /// libnetfilter_queue cannot adopt a socket: this opens a handle with a socket of its own,
+    /// then replaces that socket with a duplicate of `fd`. The handle works on the duplicate, so
+    /// `fd()` differs from `fd`, though both refer to the same socket; dropping the handle closes
+    /// the duplicate. `fd` itself is closed when the handle is dropped if `close_on_drop` is set,
+    /// and is otherwise left to the caller.
+    pub fn from_raw_fd(fd: RawFd, close_on_drop: bool) -> Result<Handle, Error> {
+        let mut protocol: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as socklen_t;
+        let res = unsafe {
+            getsockopt(fd, SOL_SOCKET, SO_PROTOCOL,
+                       &mut protocol as *mut c_int as *mut c_void, &mut len)
+        };
+        if res < 0 {
+            return Err(error(Reason::OpenHandle, "Failed to query socket protocol", Some(res)));
+        }
+        if protocol != NETLINK_NETFILTER {
+            return Err(error_with_errno(Reason::OpenHandle, "Socket is not a NETLINK_NETFILTER socket",
+                                        None, Errno(0)));
+        }
+
+        // On error, dropping `handle` closes its socket
+        let mut handle = Handle::new()?;
+        let res = unsafe { dup2(fd, handle.fd()) };
+        if res < 0 {
+            return Err(error(Reason::OpenHandle, "Failed to attach handle to socket", Some(res)));
+        }
+        if close_on_drop {
+            handle.adopted_fd = Some(fd);
+        }
+        Ok(handle)
+    }
+
+    /// Bind the handle to a `ProtocolFamily`
+    ///
+    /// This is equivalent to `bind_pf`.
+    pub fn bind(&mut self, proto: ProtocolFamily) -> Result<(), Error> {
+        self.bind_pf(proto)
+    }
+
+    /// Unbind the handle from a `ProtocolFamily`
+    ///
+    /// This is equivalent to `unbind_pf`, and shares its dangers.
+    pub fn unbind(&mut self, proto: ProtocolFamily) -> Result<(), Error> {
+        self.unbind_pf(proto)
+    }
+
+    /// Bind the nfqueue handler of a `ProtocolFamily` to this handle, with `nfq_bind_pf`
+    ///
+    /// This is separate from creating queues, and only the families passed here are bound.
+    /// If the process lacks `CAP_NET_ADMIN`, this fails with `Reason::PermissionDenied`,
+    /// and if the `nfnetlink_queue` kernel module is not loaded, with `Reason::ModuleNotLoaded`.
+    pub fn bind_pf(&mut self, proto: ProtocolFamily) -> Result<(), Error> {
+        let _lock = LOCK.lock().unwrap();
+
+        let res = unsafe { nfq_bind_pf(self.state.ptr, proto as uint16_t) };
+        if res < 0 {
+            let err = errno();
+            Err(match err.0 {
+                e if e == EPERM || e == EACCES => {
+                    error_with_errno(Reason::PermissionDenied, "Failed to bind handle: CAP_NET_ADMIN is required",
+                                     Some(res), err)
+                },
+                _ => subsystem_error(Reason::Bind, "Failed to bind handle", Some(res), err)
+            })
+        } else {
+            self.bound = true;
+            Ok(())
+        }
+    }
+
+    /// Unbind the nfqueue handler of a `ProtocolFamily`, with `nfq_unbind_pf`
+    ///
+    /// This should usually be avoided: the handler is shared by the whole kernel,
+    /// so unbinding e.g. `INET` can disrupt other programs queueing IPv4 packets.
+    /// Some older kernels required unbinding before binding; newer ones do not.
+    pub fn unbind_pf(&mut self, proto: ProtocolFamily) -> Result<(), Error> {
+        let _lock = LOCK.lock().unwrap();
+
+        let res = unsafe { nfq_unbind_pf(self.state.ptr, proto as uint16_t) };
+        if res < 0 {
+            Err(error(Reason::Unbind, "Failed to unbind handle", Some(res)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Create a new Queue
+    ///
+    /// Any number of queues may be created on one handle; `start` dispatches each packet
+    /// to the handler of the queue it arrived on.
+    /// If the queue number is bound by another process, this fails with `Reason::QueueBusy`
+    /// once the handle has bound a family, and with `Reason::PermissionDenied` before then,
+    /// as the kernel reports both with `EPERM`.
+    /// If the `nfnetlink_queue` kernel module is not loaded, this fails with `Reason::ModuleNotLoaded`.
+    pub fn queue<F: PacketHandler>(&mut self,
+                                   queue_number: u16,
+                                   handler: F) -> Result<Box<Queue<F>>, Error> {
+        Queue::new(self.state.clone(), self.bound, queue_number as uint16_t, handler)
+    }
+
+    /// Create a new Queue handling packets with a closure
+    ///
+    /// Unlike a closure returning a `Verdict`, this receives the `QueueHandle`,
+    /// so verdicts may be deferred or batched, as with a `PacketHandler`.
+    /// The closure may return `()` or a `bool` rather than an `i32`; see `HandlerResult`.
+    pub fn queue_fn<F, R>(&mut self, queue_number: u16, handler: F) -> Result<Box<Queue<FnHandler<F>>>, Error>
+        where F: FnMut(QueueHandle, Result<&Message, &Error>) -> R, R: HandlerResult {
+        Queue::new(self.state.clone(), self.bound, queue_number as uint16_t, FnHandler(handler))
+    }
+
+    /// Create a new Queue whose packets are yielded by `packets`
+    pub fn event_queue(&mut self, queue_number: u16) -> Result<Box<Queue<EventHandler>>, Error> {
+        let handler = EventHandler::new(self.events.clone());
+        Queue::new(self.state.clone(), self.bound, queue_number as uint16_t, handler)
+    }
+
+    /// Iterate over the packets of queues created with `event_queue`
+    ///
+    /// Each call to `next` blocks until a packet arrives.
+    /// This is an alternative to handling packets with a callback in `start`.
+    pub fn packets(&mut self) -> Packets<'_> {
+        let buffer = vec![0u8; self.read_buffer_size];
+        Packets {
+            handle: self,
+            buffer: buffer
+        }
+    }
+
+    /// Get the file descriptor of the underlying netlink socket
+    ///
+    /// This allows the handle to be driven by an external event loop (e.g. `epoll` or `mio`).
+    /// The descriptor is still owned by the `Handle` and is closed when it is dropped,
+    /// so callers must not close it themselves; use `into_raw_fd` to take ownership of it.
+    pub fn fd(&self) -> RawFd {
+        unsafe { nfq_fd(self.state.ptr) }
+    }
+
+    /// Consume the handle, returning its netlink socket without closing it
+    ///
+    /// The caller takes ownership of the socket and becomes responsible for closing it.
+    /// The library's handle is leaked, so any queues must be dropped beforehand.
+    pub fn into_raw_fd(self) -> RawFd {
+        self.state.close_on_drop.store(false, Ordering::Relaxed);
+        self.fd()
+    }
+
+    /// Set the size of the netlink socket's receive buffer
+    ///
+    /// A larger buffer absorbs bursts of packets which would otherwise be dropped with `ENOBUFS`.
+    /// Returns the size actually granted by the kernel, which doubles the requested size for
+    /// bookkeeping overhead, but caps the request at `net.core.rmem_max`.
+    /// A granted size of less than twice `bytes` means the cap applied;
+    /// `force_recv_buffer_size` bypasses it.
+    pub fn set_recv_buffer_size(&mut self, bytes: usize) -> Result<usize, Error> {
+        let size = cmp::min(bytes, c_int::MAX as usize) as c_int;
+        let res = unsafe {
+            setsockopt(self.fd(), SOL_SOCKET, SO_RCVBUF,
+                       &size as *const c_int as *const c_void,
+                       mem::size_of::<c_int>() as socklen_t)
+        };
+        if res < 0 {
+            return Err(error(Reason::SetSocketOption, "Failed to set receive buffer size", Some(res)));
+        }
+
+        let mut granted: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as socklen_t;
+        let res = unsafe {
+            getsockopt(self.fd(), SOL_SOCKET, SO_RCVBUF,
+                       &mut granted as *mut c_int as *mut c_void, &mut len)
+        };
+        if res < 0 {
+            Err(error(Reason::SetSocketOption, "Failed to get receive buffer size", Some(res)))
+        } else {
+            Ok(granted as usize)
+        }
+    }
+
+    /// Force the size of the netlink socket's receive buffer, with `SO_RCVBUFFORCE`
+    ///
+    /// Unlike `set_recv_buffer_size`, this bypasses the `net.core.rmem_max` cap,
+    /// but requires the process to have `CAP_NET_ADMIN`; otherwise it fails with `EPERM`.
+    /// The kernel doubles the requested size for bookkeeping overhead.
+    /// Sizes beyond `i32::MAX` are clamped to it, the largest the socket option can carry.
+    pub fn force_recv_buffer_size(&mut self, bytes: usize) -> Result<(), Error> {
+        let size = cmp::min(bytes, c_int::MAX as usize) as c_int;
+        let res = unsafe {
+            setsockopt(self.fd(), SOL_SOCKET, SO_RCVBUFFORCE,
+                       &size as *const c_int as *const c_void,
+                       mem::size_of::<c_int>() as socklen_t)
+        };
+        if res < 0 {
+            Err(error(Reason::SetSocketOption, "Failed to force receive buffer size", Some(res)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stop the kernel reporting `ENOBUFS` when the netlink socket's receive buffer overflows
+    ///
+    /// With `NETLINK_NO_ENOBUFS` enabled, packets which do not fit in the buffer are dropped silently,
+    /// so the dispatch loops never see the error; this avoids `libnfnetlink` losing track of the
+    /// socket's sequence numbers at high packet rates.
+    /// The trade-off is that drops are no longer observable: the `on_buffer_overflow` callback is
+    /// never invoked, and only the kernel's `user_dropped` counter (see `queue::kernel_stats`) records them.
+    pub fn set_no_enobufs(&mut self, enabled: bool) -> Result<(), Error> {
+        let value = enabled as c_int;
+        let res = unsafe {
+            setsockopt(self.fd(), SOL_NETLINK, NETLINK_NO_ENOBUFS,
+                       &value as *const c_int as *const c_void,
+                       mem::size_of::<c_int>() as socklen_t)
+        };
+        if res < 0 {
+            Err(error(Reason::SetSocketOption, "Failed to set NETLINK_NO_ENOBUFS", Some(res)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Set the size of the userspace buffer that packets are read into
+    ///
+    /// This is used by every dispatch loop, `start` included, and by `packets`. It must hold the
+    /// largest packet copied to userspace plus its netlink headers, or the packet is truncated
+    /// and fails to dispatch.
+    /// The default of 68KB fits any packet.
+    pub fn set_read_buffer_size(&mut self, bytes: usize) {
+        self.read_buffer_size = bytes;
+    }
+
+    /// Get the size of the userspace buffer that packets are read into
+    pub fn read_buffer_size(&self) -> usize {
+        self.read_buffer_size
+    }
+
+    /// Set a callback to be invoked when the netlink socket's receive buffer overflows
+    ///
+    /// When the kernel cannot deliver packets fast enough, it drops them and reports `ENOBUFS`.
+    /// The socket remains usable, so the dispatch loops continue after invoking `callback`,
+    /// which can be used to log or count the drops.
+    pub fn on_buffer_overflow<F: FnMut() + 'static>(&mut self, callback: F) {
+        self.overflow_callback = Some(Box::new(callback));
+    }
+
+    /// Set a callback to be invoked when a received message cannot be handled
+    ///
+    /// This is the case when `nfq_handle_packet` returns nonzero, e.g. because the message is for
+    /// a queue with no handler, or because a handler returned a negative value.
+    /// The dispatch loops continue after invoking `callback`.
+    /// Without a callback, the failure is logged at `warn`.
+    pub fn on_packet_error<F: FnMut(&Error) + 'static>(&mut self, callback: F) {
+        self.packet_error_callback = Some(Box::new(callback));
+    }
+
+    /// Get the number of packets the kernel has dropped on their way to userspace since `prev`
+    ///
+    /// `prev` is a snapshot from `queue::kernel_stats`, e.g. taken when the last overflow was handled,
+    /// so that an `on_buffer_overflow` callback can report how many packets were lost.
+    /// Drops are counted per queue, for the queue number of `prev`.
+    pub fn dropped_since(&self, prev: &KernelQueueStats) -> Result<u32, Error> {
+        kernel_stats(prev.queue_num).map(|now| now.user_dropped_since(prev))
+    }
+
+    /// Dispatch any packets waiting on the netlink socket without blocking
+    ///
+    /// This drains the socket, handing each message to the attached queues,
+    /// and returns the number of messages dispatched (`Ok(0)` if the socket would block).
+    /// It is intended to be called whenever `fd()` becomes readable.
+    pub fn process_pending(&mut self) -> Result<usize, Error> {
+        let mut buffer = self.take_read_buffer();
+        let result = self.dispatch_pending(&mut buffer);
+        self.read_buffer = buffer;
+        result
+    }
+
+    // Drain the socket into `buffer`, for `process_pending`
+    fn dispatch_pending(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let fd = self.fd();
+        let mut count = 0;
+
+        loop {
+            let rv = unsafe {
+                recv(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len() as size_t, MSG_DONTWAIT)
+            };
+            if rv < 0 {
+                let err = errno();
+                match err.0 {
+                    e if e == EAGAIN || e == EWOULDBLOCK => return Ok(count),
+                    e if e == ENOBUFS => { self.buffer_overflowed(); continue; },
+                    _ => return Err(error_with_errno(Reason::Recv, "Failed to receive packets",
+                                                     Some(rv as c_int), err))
+                }
+            }
+
+            self.handle_packet(buffer.as_mut_ptr() as *mut c_char, rv as c_int);
+            count += 1;
+        }
+    }
+
+    /// Start listening using any attached queues
+    ///
+    /// Packets are read into a buffer of `read_buffer_size` bytes; see `set_read_buffer_size`.
+    /// This only returns if receiving fails.
+    ///
+    /// `length` is deprecated and ignored: it used to size the buffer, in bits,
+    /// which truncated any packet larger than it.
+    pub fn start(&mut self, _length: u16) {
+        let mut buffer = self.take_read_buffer();
+        let fd = self.fd();
+
+        loop {
+            let rv = unsafe { recv(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len() as size_t, 0) };
+            if rv >= 0 {
+                self.handle_packet(buffer.as_mut_ptr() as *mut c_char, rv as c_int);
+            } else if errno().0 == ENOBUFS {
+                self.buffer_overflowed();
+            } else {
+                break;
+            }
+        }
+
+        self.read_buffer = buffer;
+    }
+
+    /// Start listening using any attached queues
+    ///
+    /// This behaves exactly like `start`. `P` used to size the read buffer to its payload,
+    /// and is now ignored in favour of `read_buffer_size`.
+    pub fn start_sized<P: Payload>(&mut self) {
+        self.start(0)
+    }
+
+    /// Start listening using any attached queues, receiving up to `batch` messages per syscall
+    ///
+    /// This uses `recvmmsg`, which saves a syscall per packet when packets arrive faster than
+    /// they are handled, and falls back to a single `recv` per packet if `recvmmsg` is unavailable.
+    /// Each of the `batch` messages is read into a buffer of `read_buffer_size` bytes.
+    /// This only returns if receiving fails.
+    pub fn start_batched(&mut self, batch: usize) -> Result<(), Error> {
+        let batch = cmp::max(batch, 1);
+        let size = self.read_buffer_size;
+        let mut buffer = vec![0u8; batch * size];
+        let mut iovecs: Vec<iovec> = buffer.chunks_mut(size).map(|chunk| iovec {
+            iov_base: chunk.as_mut_ptr() as *mut c_void,
+            iov_len: size as size_t
+        }).collect();
+        let mut messages: Vec<mmsghdr> = iovecs.iter_mut().map(|iov| mmsghdr {
+            msg_hdr: msghdr {
+                msg_name: null_mut(),
+                msg_namelen: 0,
+                msg_iov: iov,
+                msg_iovlen: 1,
+                msg_control: null_mut(),
+                msg_controllen: 0,
+                msg_flags: 0
+            },
+            msg_len: 0
+        }).collect();
+        let fd = self.fd();
+        let mut batched = true;
+
+        loop {
+            let count = if batched {
+                // Block until one message arrives, then take any others without waiting
+                unsafe { recvmmsg(fd, messages.as_mut_ptr(), batch as c_uint, MSG_WAITFORONE, null_mut()) }
+            } else {
+                let rv = unsafe { recv(fd, iovecs[0].iov_base, size as size_t, 0) };
+                if rv >= 0 {
+                    messages[0].msg_len = rv as c_uint;
+                    1
+                } else {
+                    -1
+                }
+            };
+            if count < 0 {
+                let err = errno();
+                match err.0 {
+                    e if e == ENOSYS && batched => { batched = false; continue; },
+                    e if e == EINTR => continue,
+                    e if e == ENOBUFS => { self.buffer_overflowed(); continue; },
+                    _ => return Err(error_with_errno(Reason::Recv, "Failed to receive packets",
+                                                     Some(count), err))
+                }
+            }
+
+            for message in &messages[..count as usize] {
+                let data = unsafe { (*message.msg_hdr.msg_iov).iov_base as *mut c_char };
+                self.handle_packet(data, message.msg_len as c_int);
+            }
+        }
+    }
+
+    /// Start listening using any attached queues, until `n` packets have been dispatched
+    ///
+    /// Returns `n` on success. If receiving fails, the error is returned instead,
+    /// and the packets dispatched before it are not counted.
+    /// This suits tests and bounded jobs, where `start` would loop forever.
+    pub fn start_n(&mut self, n: usize) -> Result<usize, Error> {
+        let mut buffer = self.take_read_buffer();
+        let fd = self.fd();
+        let mut count = 0;
+
+        while count < n {
+            let rv = unsafe { recv(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len() as size_t, 0) };
+            if rv < 0 {
+                let err = errno();
+                match err.0 {
+                    e if e == EINTR => continue,
+                    e if e == ENOBUFS => { self.buffer_overflowed(); continue; },
+                    _ => return Err(error_with_errno(Reason::Recv, "Failed to receive packets",
+                                                     Some(rv as c_int), err))
+                }
+            }
+
+            self.handle_packet(buffer.as_mut_ptr() as *mut c_char, rv as c_int);
+            count += 1;
+        }
+
+        self.read_buffer = buffer;
+        Ok(count)
+    }
+
+    /// Start listening using any attached queues, until `stop` is set
+    ///
+    /// The flag is checked between packets, and at least every 100ms while no packets arrive,
+    /// so that the loop can be shut down cleanly (e.g. on `SIGTERM`).
+    pub fn start_until(&mut self, stop: Arc<AtomicBool>) -> Result<(), Error> {
+        self.set_recv_timeout(Some(Duration::from_millis(STOP_POLL_INTERVAL_MS)))?;
+
+        let mut buffer = self.take_read_buffer();
+        let fd = self.fd();
+        let mut result = Ok(());
+
+        while !stop.load(Ordering::SeqCst) {
+            let rv = unsafe { recv(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len() as size_t, 0) };
+            if rv < 0 {
+                let err = errno();
+                match err.0 {
+                    e if e == EAGAIN || e == EWOULDBLOCK || e == EINTR => continue,
+                    e if e == ENOBUFS => { self.buffer_overflowed(); continue; },
+                    _ => {
+                        result = Err(error_with_errno(Reason::Recv, "Failed to receive packets",
+                                                      Some(rv as c_int), err));
+                        break;
+                    }
+                }
+            }
+
+            self.handle_packet(buffer.as_mut_ptr() as *mut c_char, rv as c_int);
+        }
+
+        self.read_buffer = buffer;
+        let reset = self.set_recv_timeout(None);
+        result.and(reset)
+    }
+
+    /// Start listening using any attached queues, calling `tick` every `period`
+    ///
+    /// `tick` is called whenever `period` has elapsed since it was last called,
+    /// whether or not packets arrive, so that periodic work (e.g. flushing stats) needs no timer thread.
+    /// This only returns if receiving fails.
+    pub fn start_with_timeout<F: FnMut()>(&mut self, period: Duration, mut tick: F) -> Result<(), Error> {
+        self.set_recv_timeout(Some(period))?;
+
+        let mut buffer = self.take_read_buffer();
+        let fd = self.fd();
+        let mut last_tick = Instant::now();
+
+        let result: Result<(), Error> = loop {
+            let rv = unsafe { recv(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len() as size_t, 0) };
+            if rv < 0 {
+                let err = errno();
+                match err.0 {
+                    // Timing out is expected when idle
+                    e if e == EAGAIN || e == EWOULDBLOCK || e == EINTR => (),
+                    e if e == ENOBUFS => self.buffer_overflowed(),
+                    _ => break Err(error_with_errno(Reason::Recv, "Failed to receive packets",
+                                                    Some(rv as c_int), err))
+                }
+            } else {
+                self.handle_packet(buffer.as_mut_ptr() as *mut c_char, rv as c_int);
+            }
+
+            if last_tick.elapsed() >= period {
+                tick();
+                last_tick = Instant::now();
+            }
+        };
+
+        self.read_buffer = buffer;
+        let reset = self.set_recv_timeout(None);
+        result.and(reset)
+    }
+
+    // Take the buffer kept for the dispatch loops, sized to `read_buffer_size`
+    //
+    // The loops hand it back when they return, so it is only allocated again if the size changes.
+    fn take_read_buffer(&mut self) -> Vec<u8> {
+        let mut buffer = mem::take(&mut self.read_buffer);
+        buffer.resize(self.read_buffer_size, 0);
+        buffer
+    }
+
+    // Take the oldest packet received by the `event_queue`s, if any
+    pub(crate) fn pop_event(&mut self) -> Option<Result<PacketEvent, Error>> {
+        self.events.borrow_mut().pop_front()
+    }
+
+    // Hand a received message to the attached queues, reporting it if it cannot be handled
+    fn handle_packet(&mut self, data: *mut c_char, len: c_int) {
+        let res = unsafe { nfq_handle_packet(self.state.ptr, data, len) };
+        if res == 0 {
+            return;
+        }
+
+        let err = error_with_errno(Reason::HandlePacket, "Failed to handle packet", Some(res), errno());
+        match self.packet_error_callback {
+            Some(ref mut callback) => callback(&err),
+            None => warn!("{}", err)
+        }
+    }
+
+    fn buffer_overflowed(&mut self) {
+        if let Some(ref mut callback) = self.overflow_callback {
+            callback();
+        }
+    }
+
+    // Set the timeout for `recv` on the netlink socket, or block indefinitely for `None`
+    fn set_recv_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        let timeout = timeout.unwrap_or(Duration::from_secs(0));
+        let tv = timeval {
+            tv_sec: timeout.as_secs() as time_t,
+            tv_usec: timeout.subsec_micros() as suseconds_t
+        };
+
+        let res = unsafe {
+            setsockopt(self.fd(), SOL_SOCKET, SO_RCVTIMEO,
+                       &tv as *const timeval as *const c_void,
+                       mem::size_of::<timeval>() as socklen_t)
+        };
+        if res < 0 {
+            Err(error(Reason::SetSocketOption, "Failed to set receive timeout", Some(res)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Wait up to `timeout` for any of `handles` to receive packets, then dispatch them
+///
+/// This services several handles (e.g. one per protocol family) from a single thread,
+/// calling `process_pending` on each handle whose socket is readable.
+/// It returns once the readable handles are drained, or when `timeout` elapses, so is
+/// meant to be called in a loop.
+pub fn poll_handles(handles: &mut [&mut Handle], timeout: Duration) -> Result<(), Error> {
+    let mut fds: Vec<pollfd> = handles.iter().map(|handle| pollfd {
+        fd: handle.fd(),
+        events: POLLIN,
+        revents: 0
+    }).collect();
+    let millis = cmp::min(timeout.as_millis(), c_int::MAX as u128) as c_int;
+
+    let res = unsafe { poll(fds.as_mut_ptr(), fds.len() as c_ulong, millis) };
+    if res < 0 {
+        let err = errno();
+        return match err.0 {
+            e if e == EINTR => Ok(()),
+            _ => Err(error_with_errno(Reason::Poll, "Failed to poll handles", Some(res), err))
+        };
+    }
+
+    for (handle, fd) in handles.iter_mut().zip(&fds) {
+        if fd.revents != 0 {
+            handle.process_pending()?;
+        }
+    }
+    Ok(())
+}
+
+impl IntoRawFd for Handle {
+    fn into_raw_fd(self) -> RawFd {
+        Handle::into_raw_fd(self)
+    }
+}
+
+/// An iterator over the packets of a `Handle`'s event queues
+///
+/// Each `PacketEvent` must be resolved with its `PendingVerdict`;
+/// an event dropped without a verdict leaves its packet in the kernel's queue.
+pub struct Packets<'a> {
+    handle: &'a mut Handle,
+    buffer: Vec<u8>
+}
+
+impl<'a> Iterator for Packets<'a> {
+    type Item = Result<PacketEvent, Error>;
+
+    fn next(&mut self) -> Option<Result<PacketEvent, Error>> {
+        let fd = self.handle.fd();
+
+        loop {
+            if let Some(event) = self.handle.pop_event() {
+                return Some(event);
+            }
+
+            let rv = unsafe {
+                recv(fd, self.buffer.as_mut_ptr() as *mut c_void, self.buffer.len() as size_t, 0)
+            };
+            if rv < 0 {
+                let err = errno();
+                match err.0 {
+                    e if e == EINTR => continue,
+                    e if e == ENOBUFS => { self.handle.buffer_overflowed(); continue; },
+                    _ => return Some(Err(error_with_errno(Reason::Recv, "Failed to receive packets",
+                                                          Some(rv as c_int), err)))
+                }
+            }
+
+            self.handle.handle_packet(self.buffer.as_mut_ptr() as *mut c_char, rv as c_int);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Interface name resolution
+//!
+//! Analagous to the `nlif` helpers of <http://netfilter.org/projects/libnfnetlink/>
+
+use error::*;
+use ffi::*;
+
+/// A cache of the system's network interfaces
+///
+/// This is used to resolve the interface indices of a `Message` into names, such as `eth0`.
+pub struct InterfaceCache { ptr: *mut nlif_handle }
+
+impl Drop for InterfaceCache {
+    fn drop(&mut self) {
+        unsafe { nlif_close(self.ptr) };
+    }
+}
+
+impl InterfaceCache {
+    /// Open a new cache, populated with the current interfaces
+    pub fn new() -> Result<InterfaceCache, Error> {
+        let ptr = unsafe { nlif_open() };
+        if ptr.is_null() {
+            return Err(error(Reason::OpenInterfaceCache, "Failed to open interface cache", None));
+        }
+
+        let mut cache = InterfaceCache { ptr: ptr };
+        cache.refresh()?;
+        Ok(cache)
+    }
+
+    /// Refresh the cache
+    ///
+    /// This should be called when interfaces are added, removed or renamed.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let res = unsafe { nlif_query(self.ptr) };
+        if res < 0 {
+            Err(error(Reason::QueryInterfaces, "Failed to query interfaces", Some(res)))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn ptr(&self) -> *mut nlif_handle {
+        self.ptr
+    }
+}
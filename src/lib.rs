@@ -3,11 +3,18 @@
 //! These bindings allow you to have access to the `QUEUE` and `NFQUEUE`, set in `iptables`,
 //! and write your own userspace programs to process these queues.
 #![deny(missing_docs)]
+// Fields are initialized as `field: field` throughout
+#![allow(clippy::redundant_field_names)]
 
+extern crate errno;
 extern crate libc;
+#[macro_use]
+extern crate log;
 extern crate num;
 #[macro_use]
-extern crate lazy_static;
+extern crate bitflags;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
 mod ffi;
 
@@ -18,6 +25,10 @@ pub mod error;
 pub mod handle;
 pub mod queue;
 pub mod message;
+pub mod interface;
+pub mod rate;
+#[cfg(feature = "tokio")]
+pub mod async_handle;
 
-//#[cfg(test)]
-//mod test;
+#[cfg(test)]
+mod test;
@@ -1,3 +1,3 @@
 use std::sync::Mutex;
 
-lazy_static! { pub static ref NFQ_LOCK: Mutex<()> = Mutex::new(()); }
+pub static NFQ_LOCK: Mutex<()> = Mutex::new(());
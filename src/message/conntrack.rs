@@ -0,0 +1,72 @@
+//! Conntrack association
+//!
+//! The state values mirror `enum ip_conntrack_info` of the kernel.
+use super::find_attribute;
+
+const IP_CT_ESTABLISHED: u32 = 0;
+const IP_CT_RELATED: u32 = 1;
+const IP_CT_NEW: u32 = 2;
+const IP_CT_IS_REPLY: u32 = 3;
+
+const CTA_ID: u16 = 12;
+
+/// The state of the connection a packet belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConntrackState {
+    /// The connection has seen packets in both directions
+    Established,
+    /// The packet starts a connection related to an existing one (e.g. FTP data)
+    Related,
+    /// The packet starts a new connection
+    New
+}
+
+/// The direction of a packet within its connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConntrackDirection {
+    /// The packet travels in the direction of the packet which created the connection
+    Original,
+    /// The packet travels in the reply direction
+    Reply
+}
+
+/// The conntrack entry associated with a packet
+pub struct ConntrackInfo<'a> {
+    /// The state of the connection
+    pub state: ConntrackState,
+    /// The direction of the packet
+    pub direction: ConntrackDirection,
+    /// The raw `NFQA_CT` attribute, holding the nested `CTA_*` attributes of the entry
+    pub raw: &'a [u8]
+}
+
+impl<'a> ConntrackInfo<'a> {
+    #[doc(hidden)]
+    pub fn new(ctinfo: u32, raw: &'a [u8]) -> Option<ConntrackInfo<'a>> {
+        let (state, direction) = if ctinfo >= IP_CT_IS_REPLY {
+            (ctinfo - IP_CT_IS_REPLY, ConntrackDirection::Reply)
+        } else {
+            (ctinfo, ConntrackDirection::Original)
+        };
+        let state = match state {
+            IP_CT_ESTABLISHED => ConntrackState::Established,
+            IP_CT_RELATED => ConntrackState::Related,
+            IP_CT_NEW => ConntrackState::New,
+            _ => return None
+        };
+
+        Some(ConntrackInfo {
+            state: state,
+            direction: direction,
+            raw: raw
+        })
+    }
+
+    /// The id of the conntrack entry, shared by the packets of both directions of the connection
+    pub fn id(&self) -> Option<u32> {
+        match find_attribute(self.raw, CTA_ID) {
+            Some(id) if id.len() >= 4 => Some(u32::from_be_bytes([id[0], id[1], id[2], id[3]])),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Grouping packets into flows
+use std::collections::HashMap;
+use std::net::IpAddr;
+use super::{Message, Transport};
+
+/// The identity of the flow a packet belongs to
+///
+/// Packets of both directions of a connection share a `FlowId`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FlowId {
+    /// The id of the packet's conntrack entry
+    Conntrack(u32),
+    /// The 5-tuple of the packet, with the lower endpoint first
+    Tuple {
+        /// The IP protocol number
+        protocol: u8,
+        /// The lower of the two endpoints, as an address and port
+        low: (IpAddr, u16),
+        /// The higher of the two endpoints, as an address and port
+        high: (IpAddr, u16)
+    }
+}
+
+impl FlowId {
+    /// Derive the flow of a message
+    ///
+    /// The conntrack entry is used if `QueueFlags::CONNTRACK` is enabled; otherwise the 5-tuple
+    /// is parsed from the payload, with ports of 0 for protocols without them.
+    /// This is `None` if neither is available.
+    pub fn of(message: &Message) -> Option<FlowId> {
+        if let Some(id) = message.conntrack().and_then(|ct| ct.id()) {
+            return Some(FlowId::Conntrack(id));
+        }
+
+        let packet = message.parse_packet().ok()?;
+        let (sport, dport) = match packet.transport {
+            Transport::Tcp { sport, dport, .. } | Transport::Udp { sport, dport } => (sport, dport),
+            _ => (0, 0)
+        };
+        let source = (packet.saddr, sport);
+        let destination = (packet.daddr, dport);
+        let (low, high) = if source <= destination { (source, destination) } else { (destination, source) };
+        Some(FlowId::Tuple { protocol: packet.protocol.into(), low: low, high: high })
+    }
+}
+
+/// Counts the packets of each flow
+///
+/// Flows are never expired, so `remove` should be called once a flow is finished.
+#[derive(Default)]
+pub struct FlowTracker {
+    packets: HashMap<FlowId, u64>
+}
+
+impl FlowTracker {
+    /// Create a tracker without any flows
+    pub fn new() -> FlowTracker {
+        FlowTracker::default()
+    }
+
+    /// Count a message against its flow, returning the flow
+    ///
+    /// Messages whose flow cannot be derived (see `FlowId::of`) are not counted.
+    pub fn observe(&mut self, message: &Message) -> Option<FlowId> {
+        let id = FlowId::of(message)?;
+        *self.packets.entry(id).or_insert(0) += 1;
+        Some(id)
+    }
+
+    /// The number of packets observed in the flow `id`
+    pub fn packets(&self, id: &FlowId) -> u64 {
+        self.packets.get(id).cloned().unwrap_or(0)
+    }
+
+    /// The number of flows being tracked
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Whether no flows are being tracked
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Stop tracking the flow `id`, returning its packet count
+    pub fn remove(&mut self, id: &FlowId) -> Option<u64> {
+        self.packets.remove(id)
+    }
+
+    /// Iterate over the flows being tracked, with their packet counts
+    pub fn iter(&self) -> impl Iterator<Item = (&FlowId, u64)> {
+        self.packets.iter().map(|(id, &count)| (id, count))
+    }
+}
@@ -0,0 +1,111 @@
+//! ICMP header parsing
+use std::cmp;
+use error::*;
+use super::{Payload, FromBytes, IPHeader, ipv4_header_length};
+use super::checksum::{icmp_checksum, ipv4_checksum};
+use super::sealed::Sealed;
+
+const ICMP_DEST_UNREACH: u8 = 3;
+const IP_PROTOCOL_ICMP: u8 = 1;
+const IPV4_HEADER_LENGTH: usize = 20;
+const ICMP_HEADER_LENGTH: usize = 8;
+// The bytes of the original payload quoted after its header, as RFC 792 requires
+const ICMP_QUOTED_PAYLOAD: usize = 8;
+const DEFAULT_TTL: u8 = 64;
+
+#[allow(dead_code)]
+#[allow(missing_docs)]
+#[repr(C, packed)]
+/// A `Payload` to fetch and parse an ICMP header
+///
+/// The meaning of the last 4 bytes (the "rest of header") depends on the ICMP type.
+/// For echo requests and replies they hold the identifier and sequence number.
+pub struct IcmpHeader {
+    pub type_raw: u8,
+    pub code_raw: u8,
+    pub checksum_raw: u16,
+    pub rest_raw: u32
+}
+
+impl IcmpHeader {
+    /// Get the ICMP type
+    pub fn icmp_type(&self) -> u8 {
+        self.type_raw
+    }
+
+    /// Get the ICMP code, whose meaning depends on the type
+    pub fn code(&self) -> u8 {
+        self.code_raw
+    }
+
+    /// Parse the checksum
+    pub fn checksum(&self) -> u16 {
+        u16::from_be(self.checksum_raw)
+    }
+
+    /// Parse the identifier of an echo request or reply
+    pub fn identifier(&self) -> u16 {
+        (u32::from_be(self.rest_raw) >> 16) as u16
+    }
+
+    /// Parse the sequence number of an echo request or reply
+    pub fn sequence(&self) -> u16 {
+        u32::from_be(self.rest_raw) as u16
+    }
+}
+
+/// Build an ICMP destination unreachable packet in response to the IPv4 packet `original`
+///
+/// The response is a complete IPv4 packet from the original destination to the original source,
+/// quoting the original header and the first 8 bytes of its payload, with both checksums set.
+/// It can be sent with a raw socket to reject a packet which is dropped, e.g. with `code` 3
+/// (port unreachable) for UDP, or 13 (administratively prohibited) for a firewall.
+/// Responses must not be sent to ICMP errors, broadcasts or non-first fragments (RFC 1122),
+/// which the caller is responsible for checking.
+pub fn build_icmp_unreachable(original: &[u8], code: u8) -> Result<Vec<u8>, Error> {
+    let header_length = ipv4_header_length(original)?;
+    let quoted = &original[..cmp::min(original.len(), header_length + ICMP_QUOTED_PAYLOAD)];
+
+    let length = IPV4_HEADER_LENGTH + ICMP_HEADER_LENGTH + quoted.len();
+    let mut packet = vec![0u8; length];
+    {
+        let (ip, icmp) = packet.split_at_mut(IPV4_HEADER_LENGTH);
+        icmp[0] = ICMP_DEST_UNREACH;
+        icmp[1] = code;
+        icmp[ICMP_HEADER_LENGTH..].copy_from_slice(quoted);
+        let checksum = icmp_checksum(icmp);
+        icmp[2] = (checksum >> 8) as u8;
+        icmp[3] = checksum as u8;
+
+        ip[0] = 0x45;
+        ip[2] = (length >> 8) as u8;
+        ip[3] = length as u8;
+        ip[8] = DEFAULT_TTL;
+        ip[9] = IP_PROTOCOL_ICMP;
+        // Swap the addresses of the original
+        ip[12..16].copy_from_slice(&original[16..20]);
+        ip[16..20].copy_from_slice(&original[12..16]);
+        let checksum = ipv4_checksum(ip);
+        ip[10] = (checksum >> 8) as u8;
+        ip[11] = checksum as u8;
+    }
+    Ok(packet)
+}
+
+impl Payload for IcmpHeader {}
+impl Sealed for IcmpHeader {}
+impl FromBytes for IcmpHeader {}
+
+#[allow(missing_docs)]
+#[repr(C)]
+/// A `Payload` to fetch and parse both the IP and ICMP headers of a packet
+///
+/// This assumes the IP header carries no options; `Message::l4` handles packets with options.
+pub struct IPIcmpHeader {
+    pub ip: IPHeader,
+    pub icmp: IcmpHeader
+}
+
+impl Payload for IPIcmpHeader {}
+impl Sealed for IPIcmpHeader {}
+impl FromBytes for IPIcmpHeader {}
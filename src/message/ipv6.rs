@@ -0,0 +1,67 @@
+//! IPv6 header parsing
+use std::net::Ipv6Addr;
+use super::{Payload, FromBytes};
+use super::sealed::Sealed;
+
+#[allow(dead_code)]
+#[allow(missing_docs)]
+#[repr(C, packed)]
+/// A `Payload` to fetch and parse the fixed IPv6 packet header
+///
+/// Extension headers are not parsed; `next_header` reports the raw value.
+pub struct IPv6Header {
+    pub version_class_and_flow_raw: u32,
+    pub payload_length_raw: u16,
+    pub next_header_raw: u8,
+    pub hop_limit_raw: u8,
+    pub saddr_raw: [u8; 16],
+    pub daddr_raw: [u8; 16]
+}
+
+impl IPv6Header {
+    /// Parse the IP version (always 6)
+    pub fn version(&self) -> u8 {
+        (u32::from_be(self.version_class_and_flow_raw) >> 28) as u8
+    }
+
+    /// Parse the traffic class
+    pub fn traffic_class(&self) -> u8 {
+        (u32::from_be(self.version_class_and_flow_raw) >> 20) as u8
+    }
+
+    /// Parse the 20-bit flow label
+    pub fn flow_label(&self) -> u32 {
+        u32::from_be(self.version_class_and_flow_raw) & 0x000f_ffff
+    }
+
+    /// Parse the length of the payload following the fixed header, in bytes
+    pub fn payload_length(&self) -> u16 {
+        u16::from_be(self.payload_length_raw)
+    }
+
+    /// Get the raw next header value
+    ///
+    /// This is either the L4 protocol number or the type of the first extension header.
+    pub fn next_header(&self) -> u8 {
+        self.next_header_raw
+    }
+
+    /// Get the hop limit
+    pub fn hop_limit(&self) -> u8 {
+        self.hop_limit_raw
+    }
+
+    /// Parse the source address
+    pub fn saddr(&self) -> Ipv6Addr {
+        Ipv6Addr::from(self.saddr_raw)
+    }
+
+    /// Parse the destination address
+    pub fn daddr(&self) -> Ipv6Addr {
+        Ipv6Addr::from(self.daddr_raw)
+    }
+}
+
+impl Payload for IPv6Header {}
+impl Sealed for IPv6Header {}
+impl FromBytes for IPv6Header {}
@@ -0,0 +1,1041 @@
+//! Message parsing
+//!
+//! Analagous to <http://netfilter.org/projects/libnetfilter_queue/doxygen/group__Parsing.html>
+
+use libc::*;
+use std::ffi::CStr;
+use std::mem;
+use std::slice;
+use std::ptr::null_mut;
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use error::*;
+use util::*;
+use ffi::*;
+use interface::InterfaceCache;
+use handle::ProtocolFamily;
+pub use ffi::nfqnl_msg_packet_hdr as Header;
+
+pub mod checksum;
+
+mod ipv6;
+mod tcp;
+mod udp;
+mod icmp;
+mod conntrack;
+mod vlan;
+mod parsed;
+mod extract;
+mod flow;
+pub use self::ipv6::IPv6Header;
+pub use self::conntrack::{ConntrackInfo, ConntrackState, ConntrackDirection};
+pub use self::icmp::{IcmpHeader, IPIcmpHeader, build_icmp_unreachable};
+pub use self::tcp::{TcpHeader, IPTcpHeader, TcpOptionsIter, clamp_mss};
+pub use self::udp::{UdpHeader, IPUdpHeader};
+pub use self::vlan::VlanTag;
+pub use self::parsed::{ParsedPacket, Transport};
+pub use self::extract::{ExtractOpts, ExtractedPacket};
+pub use self::flow::{FlowId, FlowTracker};
+
+/// Structs impl'ing `Payload` must be sized correctly for the payload data that mill be transmuted to it
+pub trait Payload {}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A `Payload` which is plain-old-data, so may be safely viewed from any bytes of sufficient length
+///
+/// This is sealed, and implemented for the header types of this crate.
+pub trait FromBytes: Payload + sealed::Sealed {}
+
+#[allow(dead_code)]
+#[allow(missing_docs)]
+#[repr(C, packed)]
+/// A `Payload` to fetch and parse an IP packet header
+pub struct IPHeader {
+    pub version_and_header_raw: u8,
+    pub dscp_raw: u8,
+    pub total_length_raw: u16,
+    pub id_raw: u16,
+    pub flags_and_offset_raw: u16,
+    pub ttl_raw: u8,
+    pub protocol_raw: u8,
+    pub checksum_raw: u16,
+    pub saddr_raw: u32,
+    pub daddr_raw: u32
+}
+
+/// An `IPHeader` with every field parsed into host order, created by `IPHeader::to_host`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IPHeaderHost {
+    /// The IP version (always 4)
+    pub version: u8,
+    /// The length of the header, including options, in bytes
+    pub header_length: usize,
+    /// The Differentiated Services Code Point
+    pub dscp: u8,
+    /// The Explicit Congestion Notification
+    pub ecn: u8,
+    /// The total length of the packet, including the header, in bytes
+    pub total_length: u16,
+    /// The identification of the packet, shared by its fragments
+    pub id: u16,
+    /// Whether the Don't Fragment flag is set
+    pub dont_fragment: bool,
+    /// Whether the More Fragments flag is set
+    pub more_fragments: bool,
+    /// The offset of this fragment's data in the original packet, in bytes
+    pub fragment_offset: u16,
+    /// The Time To Live
+    pub ttl: u8,
+    /// The protocol of the payload following the header
+    pub protocol: Protocol,
+    /// The header checksum
+    pub checksum: u16,
+    /// The source address
+    pub saddr: Ipv4Addr,
+    /// The destination address
+    pub daddr: Ipv4Addr
+}
+
+impl IPHeader {
+    /// Parse every field into an `IPHeaderHost`
+    ///
+    /// The `_raw` fields are in network order, so this avoids a forgotten `from_be`.
+    pub fn to_host(&self) -> IPHeaderHost {
+        IPHeaderHost {
+            version: self.version_and_header_raw >> 4,
+            header_length: self.header_length(),
+            dscp: self.dscp(),
+            ecn: self.ecn(),
+            total_length: self.total_length(),
+            id: u16::from_be(self.id_raw),
+            dont_fragment: self.dont_fragment(),
+            more_fragments: self.more_fragments(),
+            fragment_offset: self.fragment_offset(),
+            ttl: self.ttl(),
+            protocol: self.protocol(),
+            checksum: u16::from_be(self.checksum_raw),
+            saddr: self.saddr(),
+            daddr: self.daddr()
+        }
+    }
+
+    /// Parse the source address
+    pub fn saddr(&self) -> Ipv4Addr {
+        addr_to_ipv4(self.saddr_raw)
+    }
+
+    /// Parse the destination address
+    pub fn daddr(&self) -> Ipv4Addr {
+        addr_to_ipv4(self.daddr_raw)
+    }
+
+    /// Parse the protocol of the payload following the header
+    pub fn protocol(&self) -> Protocol {
+        Protocol::from(self.protocol_raw)
+    }
+
+    /// Parse the length of the header, including options, in bytes
+    ///
+    /// This is where the L4 header begins, and is only 20 if the header carries no options.
+    pub fn header_length(&self) -> usize {
+        ip_header_length(self.version_and_header_raw)
+    }
+
+    /// Parse the total length of the packet, including the header, in bytes
+    ///
+    /// The L4 segment is `total_length() - header_length()` bytes long.
+    pub fn total_length(&self) -> u16 {
+        u16::from_be(self.total_length_raw)
+    }
+
+    /// Parse the Time To Live
+    pub fn ttl(&self) -> u8 {
+        self.ttl_raw
+    }
+
+    /// Copy the header into a new byte array with the TTL decremented and the checksum fixed
+    ///
+    /// This is for re-injecting a routed packet: copy the payload, replace its first 20 bytes
+    /// with the returned header, and pass it to `Verdict::set_verdict_payload`.
+    /// The checksum covers only these 20 bytes, so this is only correct for headers
+    /// without options (`header_length() == 20`).
+    /// A TTL of 0 is left as it is; packets with a `ttl()` of 1 or less should be dropped
+    /// rather than forwarded, to avoid forwarding loops.
+    pub fn with_decremented_ttl(&self) -> [u8; 20] {
+        let mut bytes = self.to_bytes();
+        bytes[IP_TTL_OFFSET] = self.ttl_raw.saturating_sub(1);
+        let checksum = checksum::ipv4_checksum(&bytes).to_be_bytes();
+        bytes[IP_CHECKSUM_OFFSET] = checksum[0];
+        bytes[IP_CHECKSUM_OFFSET + 1] = checksum[1];
+        bytes
+    }
+
+    // The header as it appears on the wire, since the raw fields are kept in network order
+    fn to_bytes(&self) -> [u8; 20] {
+        let mut bytes = [0u8; 20];
+        bytes[0] = self.version_and_header_raw;
+        bytes[1] = self.dscp_raw;
+        bytes[2..4].copy_from_slice(&self.total_length_raw.to_ne_bytes());
+        bytes[4..6].copy_from_slice(&self.id_raw.to_ne_bytes());
+        bytes[6..8].copy_from_slice(&self.flags_and_offset_raw.to_ne_bytes());
+        bytes[IP_TTL_OFFSET] = self.ttl_raw;
+        bytes[IP_PROTOCOL_OFFSET] = self.protocol_raw;
+        bytes[10..12].copy_from_slice(&self.checksum_raw.to_ne_bytes());
+        bytes[12..16].copy_from_slice(&self.saddr_raw.to_ne_bytes());
+        bytes[16..20].copy_from_slice(&self.daddr_raw.to_ne_bytes());
+        bytes
+    }
+
+    /// Parse the Differentiated Services Code Point, the top 6 bits of the DS field
+    pub fn dscp(&self) -> u8 {
+        self.dscp_raw >> 2
+    }
+
+    /// Parse the Explicit Congestion Notification, the bottom 2 bits of the DS field
+    pub fn ecn(&self) -> u8 {
+        self.dscp_raw & 0x03
+    }
+
+    /// Set the Differentiated Services Code Point, keeping the ECN bits
+    ///
+    /// Only the bottom 6 bits of `dscp` are used.
+    /// The header checksum must be recomputed (see `checksum::ipv4_checksum`) before the
+    /// packet is re-injected with `Verdict::set_verdict_payload`.
+    pub fn set_dscp(&mut self, dscp: u8) {
+        self.dscp_raw = (dscp << 2) | self.ecn();
+    }
+
+    /// Whether the Don't Fragment flag is set
+    pub fn dont_fragment(&self) -> bool {
+        u16::from_be(self.flags_and_offset_raw) & IP_DF != 0
+    }
+
+    /// Whether the More Fragments flag is set, so that this is not the last fragment
+    pub fn more_fragments(&self) -> bool {
+        u16::from_be(self.flags_and_offset_raw) & IP_MF != 0
+    }
+
+    /// Parse the offset of this fragment's data in the original packet, in bytes
+    ///
+    /// Only the first fragment, at offset 0, carries the L4 header.
+    pub fn fragment_offset(&self) -> u16 {
+        (u16::from_be(self.flags_and_offset_raw) & IP_OFFSET_MASK) * 8
+    }
+
+    /// Whether this packet is a fragment of a larger packet
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.fragment_offset() != 0
+    }
+}
+
+/// The protocol carried in an IP packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// Internet Control Message Protocol
+    Icmp,
+    /// Transmission Control Protocol
+    Tcp,
+    /// User Datagram Protocol
+    Udp,
+    /// Any other protocol, by number
+    Unknown(u8)
+}
+
+impl From<u8> for Protocol {
+    fn from(protocol: u8) -> Protocol {
+        match protocol {
+            1 => Protocol::Icmp,
+            6 => Protocol::Tcp,
+            17 => Protocol::Udp,
+            p => Protocol::Unknown(p)
+        }
+    }
+}
+
+impl From<Protocol> for u8 {
+    fn from(protocol: Protocol) -> u8 {
+        match protocol {
+            Protocol::Icmp => 1,
+            Protocol::Tcp => 6,
+            Protocol::Udp => 17,
+            Protocol::Unknown(p) => p
+        }
+    }
+}
+
+impl Protocol {
+    /// Whether the protocol is one with a variant of its own, rather than `Unknown`
+    pub fn is_known(&self) -> bool {
+        !matches!(*self, Protocol::Unknown(_))
+    }
+}
+
+/// The L4 header of an IP packet, parsed according to its `Protocol`
+pub enum L4<'a> {
+    /// A TCP header
+    Tcp(&'a TcpHeader),
+    /// A UDP header
+    Udp(&'a UdpHeader),
+    /// An ICMP header
+    Icmp(&'a IcmpHeader),
+    /// A protocol without a parser
+    Other(Protocol)
+}
+
+// The raw address is in network order, so is read as a host-order integer first
+#[inline]
+fn addr_to_ipv4(src: u32) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from_be(src))
+}
+
+impl Payload for IPHeader {}
+impl sealed::Sealed for IPHeader {}
+impl FromBytes for IPHeader {}
+
+// The headers are viewed directly over the wire bytes, so must have exactly the wire sizes
+const _: [(); 20] = [(); mem::size_of::<IPHeader>()];
+const _: [(); 40] = [(); mem::size_of::<IPv6Header>()];
+const _: [(); 20] = [(); mem::size_of::<TcpHeader>()];
+const _: [(); 8] = [(); mem::size_of::<UdpHeader>()];
+const _: [(); 8] = [(); mem::size_of::<IcmpHeader>()];
+const _: [(); 40] = [(); mem::size_of::<IPTcpHeader>()];
+const _: [(); 28] = [(); mem::size_of::<IPUdpHeader>()];
+const _: [(); 28] = [(); mem::size_of::<IPIcmpHeader>()];
+
+const IP_TTL_OFFSET: usize = 8;
+const IP_PROTOCOL_OFFSET: usize = 9;
+const IP_CHECKSUM_OFFSET: usize = 10;
+
+const IP_DF: u16 = 0x4000;
+const IP_MF: u16 = 0x2000;
+const IP_OFFSET_MASK: u16 = 0x1fff;
+
+// The IHL field is the low nibble, counting 32-bit words
+#[inline]
+fn ip_header_length(version_and_header: u8) -> usize {
+    (version_and_header & 0x0f) as usize * 4
+}
+
+// The length of the header of the IPv4 packet starting at `bytes`, which must all have been copied
+fn ipv4_header_length(bytes: &[u8]) -> Result<usize, Error> {
+    if bytes.len() < mem::size_of::<IPHeader>() {
+        return Err(payload_too_short(bytes.len(), mem::size_of::<IPHeader>()));
+    }
+    if bytes[0] >> 4 != 4 {
+        return Err(error(Reason::InvalidPacket, "Packet is not IPv4", None));
+    }
+    let length = ip_header_length(bytes[0]);
+    if length < mem::size_of::<IPHeader>() {
+        return Err(error(Reason::InvalidPacket, "Invalid IP header length", None));
+    }
+    if bytes.len() < length {
+        return Err(payload_too_short(bytes.len(), length));
+    }
+    Ok(length)
+}
+
+// The length of the IP packet starting at `bytes`, according to its header
+#[doc(hidden)]
+pub fn ip_packet_length(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() {
+        return None;
+    }
+    match bytes[0] >> 4 {
+        4 if bytes.len() >= 4 => Some(((bytes[2] as usize) << 8) | bytes[3] as usize),
+        // The IPv6 payload length excludes the 40-byte fixed header
+        6 if bytes.len() >= 6 => Some((((bytes[4] as usize) << 8) | bytes[5] as usize) + 40),
+        _ => None
+    }
+}
+
+// The application-layer bytes of the IPv4 packet starting at `bytes`, after its TCP or UDP header
+#[doc(hidden)]
+pub fn l4_payload(bytes: &[u8]) -> Result<&[u8], Error> {
+    let ip_length = ipv4_header_length(bytes)?;
+
+    // The L4 headers are packed, so need no alignment
+    let l4_length = unsafe {
+        match Protocol::from(bytes[IP_PROTOCOL_OFFSET]) {
+            Protocol::Tcp => {
+                let length = view::<TcpHeader>(&bytes[ip_length..])?.data_offset() as usize * 4;
+                if length < mem::size_of::<TcpHeader>() {
+                    return Err(error(Reason::InvalidPacket, "Invalid TCP header length", None));
+                }
+                length
+            },
+            Protocol::Udp => view::<UdpHeader>(&bytes[ip_length..]).map(|_| mem::size_of::<UdpHeader>())?,
+            _ => return Err(error(Reason::InvalidPacket, "Packet is neither TCP nor UDP", None))
+        }
+    };
+
+    let offset = ip_length + l4_length;
+    if bytes.len() < offset {
+        return Err(payload_too_short(bytes.len(), offset));
+    }
+    // Frames padded to the minimum Ethernet length carry bytes beyond the IP packet
+    let end = match ip_packet_length(bytes) {
+        Some(length) if length >= offset && length < bytes.len() => length,
+        _ => bytes.len()
+    };
+    Ok(&bytes[offset..end])
+}
+
+// The length of a netlink header, which precedes the `nfgenmsg` of a message
+const NLMSG_HDRLEN: usize = 16;
+// The length of an `nfgenmsg`, which precedes the attributes of a message
+const NFGENMSG_LEN: usize = 4;
+// The flags in the high bits of an attribute's type
+const NFA_TYPE_MASK: u16 = 0x3fff;
+
+// An iterator over a sequence of netlink attributes, yielding the type and data of each
+//
+// It stops at the first malformed attribute.
+pub(crate) struct Attributes<'a> {
+    rest: &'a [u8]
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        let header = mem::size_of::<nfattr>();
+        let rest = self.rest;
+        if rest.len() < header {
+            return None;
+        }
+        let len = u16::from_ne_bytes([rest[0], rest[1]]) as usize;
+        let kind = u16::from_ne_bytes([rest[2], rest[3]]) & NFA_TYPE_MASK;
+        if len < header || len > rest.len() {
+            self.rest = &[];
+            return None;
+        }
+        // Attributes are aligned to 4 bytes
+        let aligned = (len + 3) & !3;
+        self.rest = if aligned >= rest.len() { &[] } else { &rest[aligned..] };
+        Some((kind, &rest[header..len]))
+    }
+}
+
+pub(crate) fn attributes(bytes: &[u8]) -> Attributes<'_> {
+    Attributes { rest: bytes }
+}
+
+// Find the attribute `attr` in a sequence of netlink attributes
+#[doc(hidden)]
+pub fn find_attribute(bytes: &[u8], attr: u16) -> Option<&[u8]> {
+    attributes(bytes).find(|&(kind, _)| kind == attr).map(|(_, data)| data)
+}
+
+// View the start of `bytes` as a `Payload`, if there are enough of them
+// The caller must ensure that `bytes` is suitably aligned for `A`
+#[doc(hidden)]
+pub unsafe fn view<A: Payload>(bytes: &[u8]) -> Result<&A, Error> {
+    if bytes.len() < mem::size_of::<A>() {
+        Err(payload_too_short(bytes.len(), mem::size_of::<A>()))
+    } else {
+        Ok(&*(bytes.as_ptr() as *const A))
+    }
+}
+
+// The initial size of the buffer for `to_xml`, which is doubled until the output fits
+const XML_BUFFER_SIZE: usize = 1024;
+
+bitflags! {
+    /// The fields of a message for `Message::to_xml` to print
+    pub struct XmlFlags: u32 {
+        /// The hardware address
+        const HW = 1 << 0;
+        /// The nfmark
+        const MARK = 1 << 1;
+        /// The input and output interfaces
+        const DEV = 1 << 2;
+        /// The physical input and output interfaces
+        const PHYSDEV = 1 << 3;
+        /// The payload, in hex
+        const PAYLOAD = 1 << 4;
+        /// The timestamp
+        const TIME = 1 << 5;
+        /// The uid of the owning socket
+        const UID = 1 << 6;
+        /// The gid of the owning socket
+        const GID = 1 << 7;
+        /// The security context
+        const SECCTX = 1 << 8;
+        /// Every field
+        const ALL = !0;
+    }
+}
+
+// Interface indices of 0 are returned when no interface is applicable
+#[inline]
+fn dev_index(index: u32) -> Option<u32> {
+    if index == 0 { None } else { Some(index) }
+}
+
+const IFNAMSIZ: usize = 16;
+
+type DevNameFn = unsafe extern "C" fn(*mut nlif_handle, *mut nfq_data, *mut c_char) -> c_int;
+
+fn dev_name(get_name: DevNameFn, cache: &InterfaceCache, ptr: *mut nfq_data) -> Option<String> {
+    let mut name = [0 as c_char; IFNAMSIZ];
+    let res = unsafe { get_name(cache.ptr(), ptr, name.as_mut_ptr()) };
+    if res < 0 {
+        None
+    } else {
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
+/// The netfilter hook a packet of the IP families was queued from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    /// `NF_INET_PRE_ROUTING`, before the routing decision
+    PreRouting,
+    /// `NF_INET_LOCAL_IN`, for packets delivered to a local process
+    LocalIn,
+    /// `NF_INET_FORWARD`, for packets routed through the host
+    Forward,
+    /// `NF_INET_LOCAL_OUT`, for locally generated packets
+    LocalOut,
+    /// `NF_INET_POST_ROUTING`, after the routing decision
+    PostRouting,
+    /// Any other hook, by number
+    Unknown(u8)
+}
+
+impl From<u8> for Hook {
+    fn from(hook: u8) -> Hook {
+        match hook {
+            0 => Hook::PreRouting,
+            1 => Hook::LocalIn,
+            2 => Hook::Forward,
+            3 => Hook::LocalOut,
+            4 => Hook::PostRouting,
+            h => Hook::Unknown(h)
+        }
+    }
+}
+
+impl From<Hook> for u8 {
+    fn from(hook: Hook) -> u8 {
+        match hook {
+            Hook::PreRouting => 0,
+            Hook::LocalIn => 1,
+            Hook::Forward => 2,
+            Hook::LocalOut => 3,
+            Hook::PostRouting => 4,
+            Hook::Unknown(h) => h
+        }
+    }
+}
+
+/// The direction of a packet through the host, according to the hook it was queued from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Arriving at the host, before routing (`PREROUTING`, ARP input, or netdev ingress)
+    Inbound,
+    /// Routed to a local process (`INPUT`)
+    Local,
+    /// Routed through the host to another (`FORWARD`)
+    Forward,
+    /// Leaving the host: locally generated (`OUTPUT`), after routing (`POSTROUTING`),
+    /// ARP output, or netdev egress
+    Outbound,
+    /// A hook without a direction in the family
+    Unknown(u8)
+}
+
+impl Direction {
+    /// Map the `hook` of a packet in the `family` to its direction
+    ///
+    /// The IP and bridge families share the `NF_INET_*` hook numbers,
+    /// while ARP and netdev number their hooks separately.
+    pub fn from_hook(family: ProtocolFamily, hook: u8) -> Direction {
+        match (family, hook) {
+            (ProtocolFamily::INET, 0) | (ProtocolFamily::INET6, 0) | (ProtocolFamily::BRIDGE, 0) => Direction::Inbound,
+            (ProtocolFamily::INET, 1) | (ProtocolFamily::INET6, 1) | (ProtocolFamily::BRIDGE, 1) => Direction::Local,
+            (ProtocolFamily::INET, 2) | (ProtocolFamily::INET6, 2) | (ProtocolFamily::BRIDGE, 2) => Direction::Forward,
+            (ProtocolFamily::INET, 3) | (ProtocolFamily::INET6, 3) | (ProtocolFamily::BRIDGE, 3) |
+            (ProtocolFamily::INET, 4) | (ProtocolFamily::INET6, 4) | (ProtocolFamily::BRIDGE, 4) => Direction::Outbound,
+            (ProtocolFamily::ARP, 0) | (ProtocolFamily::NETDEV, 0) => Direction::Inbound,
+            (ProtocolFamily::ARP, 1) | (ProtocolFamily::NETDEV, 1) => Direction::Outbound,
+            (ProtocolFamily::ARP, 2) => Direction::Forward,
+            (_, hook) => Direction::Unknown(hook)
+        }
+    }
+}
+
+/// Information about the socket buffer holding a packet
+#[derive(Clone, Copy, Debug)]
+pub struct SkbInfo {
+    /// The raw `NFQA_SKB_*` flags
+    pub raw: u32
+}
+
+impl SkbInfo {
+    /// Whether the checksum has not yet been computed
+    ///
+    /// This is the case for locally-generated packets with checksum offload,
+    /// so their checksums should not be validated.
+    pub fn checksum_not_ready(&self) -> bool {
+        self.raw & NFQA_SKB_CSUMNOTREADY != 0
+    }
+
+    /// Whether the packet is a GSO packet, which may be larger than the MTU
+    pub fn is_gso(&self) -> bool {
+        self.raw & NFQA_SKB_GSO != 0
+    }
+
+    /// Whether the checksum has not been verified by the kernel or hardware
+    pub fn checksum_not_verified(&self) -> bool {
+        self.raw & NFQA_SKB_CSUM_NOTVERIFIED != 0
+    }
+}
+
+/// The packet message
+pub struct Message<'a> {
+    /// A raw pointer to the queue data
+    pub raw: *mut nfgenmsg,
+    /// A raw pointer to the packet data
+    pub ptr: *mut nfq_data,
+    /// The `Message` header
+    ///
+    /// A verdict cannot be set without the packet's id
+    /// parsed from the header.
+    /// For convenience, the header is always copied into the message.
+    pub header: Header,
+    // The payload of a message synthesized by `from_bytes`, which has no packet data
+    bytes: Option<&'a [u8]>
+}
+
+impl<'a> Drop for Message<'a> {
+    fn drop(&mut self) {}
+}
+
+impl<'a> Message<'a> {
+    /// Wrap the message passed to a queue's callback
+    ///
+    /// # Safety
+    ///
+    /// `raw` and `ptr` must be those passed to the callback, and outlive the message.
+    #[doc(hidden)]
+    pub unsafe fn new(raw: *mut nfgenmsg, ptr: *mut nfq_data) -> Result<Message<'a>, Error> {
+        if ptr.is_null() {
+            return Err(error(Reason::GetHeader, "Message has no packet data", None));
+        }
+        let header = {
+            let ptr = nfq_get_msg_packet_hdr(ptr);
+            match as_ref(&ptr) {
+                Some(h) => *h,
+                None => return Err(error(Reason::GetHeader, "Failed to get header", None))
+            }
+        };
+        Ok(Message {
+            raw: raw,
+            ptr: ptr,
+            header: header,
+            bytes: None
+        })
+    }
+
+    /// Synthesize a message for the packet `id` carrying `bytes`, without a queue
+    ///
+    /// This allows a `PacketHandler` to be unit-tested with crafted packets.
+    /// Only the payload is available: `payload_bytes` and the parsers built on it work as usual,
+    /// while the metadata accessors report nothing (e.g. `mark` is 0, and `in_dev` is `None`).
+    #[cfg(any(test, feature = "testing"))]
+    pub fn from_bytes(id: u32, bytes: &'a [u8]) -> Message<'a> {
+        Message {
+            raw: null_mut(),
+            ptr: null_mut(),
+            header: Header { packet_id: id.to_be(), hw_protocol: 0, hook: 0 },
+            bytes: Some(bytes)
+        }
+    }
+
+    // The packet data of the message, which a message synthesized by `from_bytes` lacks
+    fn data(&self) -> Option<*mut nfq_data> {
+        if self.ptr.is_null() { None } else { Some(self.ptr) }
+    }
+
+    /// Get the hook the packet was queued from
+    ///
+    /// The hooks are numbered as for the IP families, which the bridge family shares;
+    /// for the ARP and netdev families, use `header.hook()` or `direction`.
+    pub fn hook(&self) -> Hook {
+        Hook::from(self.header.hook())
+    }
+
+    /// Get the direction of the packet, given the `family` its queue is bound for
+    pub fn direction(&self, family: ProtocolFamily) -> Direction {
+        Direction::from_hook(family, self.header.hook())
+    }
+
+    /// Get the nfmark of the packet
+    ///
+    /// A packet which has not been marked has an nfmark of 0.
+    pub fn mark(&self) -> u32 {
+        self.data().map(|ptr| unsafe { nfq_get_nfmark(ptr) }).unwrap_or(0)
+    }
+
+    /// Get the time at which the kernel received the packet
+    ///
+    /// Not every packet is timestamped, in which case this returns an `Error`.
+    pub fn timestamp(&self) -> Result<SystemTime, Error> {
+        let ptr = match self.data() {
+            Some(ptr) => ptr,
+            None => return Err(error(Reason::GetTimestamp, "Message has no packet data", None))
+        };
+        let mut tv = timeval { tv_sec: 0, tv_usec: 0 };
+        let res = unsafe { nfq_get_timestamp(ptr, &mut tv) };
+        if res != 0 {
+            Err(error(Reason::GetTimestamp, "Failed to get timestamp", Some(res)))
+        } else {
+            Ok(UNIX_EPOCH + Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000))
+        }
+    }
+
+    /// Get the index of the interface the packet arrived on
+    ///
+    /// This is `None` when not applicable, e.g. for locally-generated packets.
+    pub fn in_dev(&self) -> Option<u32> {
+        self.data().and_then(|ptr| dev_index(unsafe { nfq_get_indev(ptr) }))
+    }
+
+    /// Get the index of the interface the packet will be sent on
+    ///
+    /// This is `None` when not applicable, e.g. in the input hook.
+    pub fn out_dev(&self) -> Option<u32> {
+        self.data().and_then(|ptr| dev_index(unsafe { nfq_get_outdev(ptr) }))
+    }
+
+    /// Get the index of the physical interface the packet arrived on
+    ///
+    /// This differs from `in_dev` when the packet arrived on a bridge.
+    pub fn phys_in_dev(&self) -> Option<u32> {
+        self.data().and_then(|ptr| dev_index(unsafe { nfq_get_physindev(ptr) }))
+    }
+
+    /// Get the index of the physical interface the packet will be sent on
+    ///
+    /// This differs from `out_dev` when the packet will be sent on a bridge.
+    pub fn phys_out_dev(&self) -> Option<u32> {
+        self.data().and_then(|ptr| dev_index(unsafe { nfq_get_physoutdev(ptr) }))
+    }
+
+    /// Get the name of the interface the packet arrived on
+    pub fn in_dev_name(&self, cache: &InterfaceCache) -> Option<String> {
+        self.in_dev().and_then(|_| dev_name(nfq_get_indev_name, cache, self.ptr))
+    }
+
+    /// Get the name of the interface the packet will be sent on
+    pub fn out_dev_name(&self, cache: &InterfaceCache) -> Option<String> {
+        self.out_dev().and_then(|_| dev_name(nfq_get_outdev_name, cache, self.ptr))
+    }
+
+    /// Get the name of the physical interface the packet arrived on
+    pub fn phys_in_dev_name(&self, cache: &InterfaceCache) -> Option<String> {
+        self.phys_in_dev().and_then(|_| dev_name(nfq_get_physindev_name, cache, self.ptr))
+    }
+
+    /// Get the name of the physical interface the packet will be sent on
+    pub fn phys_out_dev_name(&self, cache: &InterfaceCache) -> Option<String> {
+        self.phys_out_dev().and_then(|_| dev_name(nfq_get_physoutdev_name, cache, self.ptr))
+    }
+
+    /// Get the source hardware (MAC) address of the packet
+    ///
+    /// This is only available where link-layer information is present,
+    /// e.g. not for packets in the output or postrouting hooks.
+    pub fn hw_addr(&self) -> Option<[u8; 6]> {
+        let data = self.data()?;
+        let hw = unsafe {
+            let ptr = nfq_get_packet_hw(data);
+            as_ref(&ptr)?
+        };
+        if u16::from_be(hw.hw_addrlen) < 6 {
+            return None;
+        }
+
+        let mut addr = [0u8; 6];
+        addr.copy_from_slice(&hw.hw_addr[..6]);
+        Some(addr)
+    }
+
+    /// Get the uid of the socket which owns the packet
+    ///
+    /// This is only available for locally-generated packets,
+    /// once `QueueFlags::UID_GID` has been enabled with `queue.set_flags`.
+    pub fn uid(&self) -> Option<u32> {
+        let ptr = self.data()?;
+        let mut uid: uint32_t = 0;
+        match unsafe { nfq_get_uid(ptr, &mut uid) } {
+            0 => None,
+            _ => Some(uid)
+        }
+    }
+
+    /// Get the gid of the socket which owns the packet
+    ///
+    /// This is only available for locally-generated packets,
+    /// once `QueueFlags::UID_GID` has been enabled with `queue.set_flags`.
+    pub fn gid(&self) -> Option<u32> {
+        let ptr = self.data()?;
+        let mut gid: uint32_t = 0;
+        match unsafe { nfq_get_gid(ptr, &mut gid) } {
+            0 => None,
+            _ => Some(gid)
+        }
+    }
+
+    /// Get the security context of the packet
+    ///
+    /// This is only available once `QueueFlags::SECCTX` has been enabled with `queue.set_flags`.
+    pub fn secctx(&self) -> Option<String> {
+        let ptr = self.data()?;
+        let mut data: *mut c_uchar = null_mut();
+        let len = unsafe { nfq_get_secctx(ptr, &mut data) };
+        if len <= 0 || data.is_null() {
+            return None;
+        }
+
+        let bytes = unsafe { slice::from_raw_parts(data as *const u8, len as usize) };
+        // The context may be NUL-terminated
+        let bytes = match bytes.iter().position(|&b| b == 0) {
+            Some(end) => &bytes[..end],
+            None => bytes
+        };
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// Get information about the socket buffer holding the packet
+    pub fn skb_info(&self) -> SkbInfo {
+        SkbInfo { raw: self.data().map(|ptr| unsafe { nfq_get_skbinfo(ptr) }).unwrap_or(0) }
+    }
+
+    /// Whether the packet is a GSO packet, holding several segments
+    ///
+    /// This is only the case once `QueueFlags::GSO` has been enabled with `queue.set_flags`.
+    /// The payload of a GSO packet may then be far larger than the MTU, and its IP and L4 headers
+    /// describe the whole packet rather than any one segment. The kernel does not report the size
+    /// of the segments it will be split into.
+    pub fn is_gso(&self) -> bool {
+        self.skb_info().is_gso()
+    }
+
+    /// Get the 802.1Q VLAN tag of the frame
+    ///
+    /// This is only available for tagged frames, on kernels which report `NFQA_VLAN` (4.7 and later).
+    pub fn vlan(&self) -> Option<VlanTag> {
+        self.message_attribute(NFQA_VLAN).and_then(VlanTag::parse)
+    }
+
+    /// Get the conntrack entry associated with the packet
+    ///
+    /// This is only available once `QueueFlags::CONNTRACK` has been enabled with `queue.set_flags`.
+    pub fn conntrack(&self) -> Option<ConntrackInfo<'_>> {
+        let info = match self.message_attribute(NFQA_CT_INFO) {
+            Some(info) if info.len() >= 4 => info,
+            _ => return None
+        };
+        let ctinfo = (info[0] as u32) << 24 | (info[1] as u32) << 16 | (info[2] as u32) << 8 | info[3] as u32;
+        let raw = self.message_attribute(NFQA_CT)?;
+        ConntrackInfo::new(ctinfo, raw)
+    }
+
+    /// Format what the kernel reported about the packet as XML, with `nfq_snprintf_xml`
+    ///
+    /// This is intended for logging and debugging; `flags` selects the fields to print.
+    pub fn to_xml(&self, flags: XmlFlags) -> Result<String, Error> {
+        let ptr = match self.data() {
+            Some(ptr) => ptr,
+            None => return Err(error(Reason::FormatXml, "Message has no packet data", None))
+        };
+
+        let mut buffer: Vec<u8> = vec![0; XML_BUFFER_SIZE];
+        loop {
+            let res = unsafe {
+                nfq_snprintf_xml(buffer.as_mut_ptr() as *mut c_char, buffer.len() as size_t,
+                                 ptr, flags.bits() as c_int)
+            };
+            if res < 0 {
+                return Err(error(Reason::FormatXml, "Failed to format packet", Some(res)));
+            }
+            // Like `snprintf`, the result excludes the NUL, and is the length the output would have had
+            if (res as usize) < buffer.len() {
+                buffer.truncate(res as usize);
+                return Ok(String::from_utf8_lossy(&buffer).into_owned());
+            }
+            let len = buffer.len() * 2;
+            buffer.resize(len, 0);
+        }
+    }
+
+    // Read the raw data of a netlink attribute by walking the message itself
+    // The attribute table of `nfq_data` is private to libnetfilter_queue, and too short for newer attributes
+    fn message_attribute(&self, attr: u16) -> Option<&[u8]> {
+        find_attribute(self.message_attributes()?, attr)
+    }
+
+    // The netlink attributes of the message, which a message synthesized by `from_bytes` lacks
+    fn message_attributes(&self) -> Option<&[u8]> {
+        if self.raw.is_null() {
+            return None;
+        }
+        unsafe {
+            // The `nfgenmsg` directly follows the netlink header, which starts with the message length
+            let start = self.raw as *const u8;
+            let len = *(start.sub(NLMSG_HDRLEN) as *const u32) as usize;
+            if len < NLMSG_HDRLEN + NFGENMSG_LEN {
+                return None;
+            }
+            Some(slice::from_raw_parts(start.add(NFGENMSG_LEN),
+                                       len - NLMSG_HDRLEN - NFGENMSG_LEN))
+        }
+    }
+
+    /// Get both the header and the payload bytes of the packet
+    ///
+    /// This is a shorthand for handlers which need the packet id to set a verdict,
+    /// as well as the bytes to inspect.
+    pub fn packet(&self) -> Result<(&Header, &[u8]), Error> {
+        let payload = self.payload_bytes()?;
+        Ok((&self.header, payload))
+    }
+
+    /// Parse the IP and L4 headers of the packet into an owned `ParsedPacket`
+    ///
+    /// This handles IPv4 and IPv6, and TCP, UDP and ICMP, doing all byte-swapping.
+    /// The L4 header is left unparsed for non-first fragments, unknown protocols,
+    /// or if too little of the packet was copied to userspace.
+    pub fn parse_packet(&self) -> Result<ParsedPacket, Error> {
+        ParsedPacket::parse(self.payload_bytes()?)
+    }
+
+    /// Get the number of bytes of the packet copied to userspace
+    pub fn captured_len(&self) -> usize {
+        self.payload_bytes().map(|p| p.len()).unwrap_or(0)
+    }
+
+    /// Get the length of the packet before it was truncated to the copy range
+    ///
+    /// This is reported by the kernel when a packet is truncated, and is otherwise read from the
+    /// total length of the IPv4 or IPv6 header, so is `None` if no IP header was copied.
+    /// If it exceeds `captured_len`, the `Queue`'s `CopyMode` range is too small to hold the whole packet.
+    pub fn original_len(&self) -> Option<usize> {
+        match self.message_attribute(NFQA_CAP_LEN) {
+            Some(raw) if raw.len() >= 4 => {
+                Some((raw[0] as usize) << 24 | (raw[1] as usize) << 16 | (raw[2] as usize) << 8 | raw[3] as usize)
+            },
+            _ => self.payload_bytes().ok().and_then(ip_packet_length)
+        }
+    }
+
+    /// Get the packet payload as a byte slice
+    ///
+    /// The slice holds exactly the bytes copied to userspace, which is determined by the `Queue`'s `CopyMode`.
+    /// Unlike `payload`, this needs no assumptions about the size of the packet.
+    pub fn payload_bytes(&self) -> Result<&[u8], Error> {
+        if let Some(bytes) = self.bytes {
+            return Ok(bytes);
+        }
+
+        let ptr = match self.data() {
+            Some(ptr) => ptr,
+            None => return Err(error(Reason::GetPayload, "Message has no packet data", None))
+        };
+        let mut data: *mut c_uchar = null_mut();
+        let len = unsafe { nfq_get_payload(ptr, &mut data) };
+        if len < 0 {
+            // The message carries no payload attribute at all, rather than a short one
+            debug!("Packet {} has no payload (length: {})", self.header.id(), len);
+            Err(error(Reason::GetPayload,
+                      "Failed to get payload: none was copied, so the copy mode is not Packet",
+                      Some(len)))
+        } else if len == 0 {
+            Ok(&[])
+        } else if data.is_null() {
+            debug!("Packet {} has a payload of {} bytes, but no data", self.header.id(), len);
+            Err(error(Reason::GetPayload, &format!("Failed to get payload of {} bytes", len), Some(len)))
+        } else {
+            Ok(unsafe { slice::from_raw_parts(data as *const u8, len as usize) })
+        }
+    }
+
+    /// Parse the L4 header of an IPv4 packet, choosing the parser by the IP protocol
+    ///
+    /// Enough of the packet must be copied to userspace for both headers, including any IP options.
+    /// The L4 header is found after the IP header's options, according to its `header_length`.
+    /// Packets of other IP versions fail with `Reason::InvalidPacket`; see `parse_packet` for IPv6.
+    pub fn l4(&self) -> Result<L4<'_>, Error> {
+        let bytes = self.payload_bytes()?;
+        let offset = ipv4_header_length(bytes)?;
+
+        // The L4 headers are packed, so need no alignment
+        let l4 = &bytes[offset..];
+        unsafe {
+            match Protocol::from(bytes[IP_PROTOCOL_OFFSET]) {
+                Protocol::Tcp => view::<TcpHeader>(l4).map(L4::Tcp),
+                Protocol::Udp => view::<UdpHeader>(l4).map(L4::Udp),
+                Protocol::Icmp => view::<IcmpHeader>(l4).map(L4::Icmp),
+                protocol => Ok(L4::Other(protocol))
+            }
+        }
+    }
+
+    /// Get the application-layer bytes of an IPv4 TCP or UDP packet, after both headers
+    ///
+    /// The L4 header is found after the IP header's options, and a TCP header's length is read
+    /// from its data offset, so options of either are skipped.
+    /// If the captured bytes do not reach the end of the headers, an `Error` is returned;
+    /// otherwise the payload may still be cut short by the `Queue`'s `CopyMode` range.
+    /// Packets of other IP versions fail with `Reason::InvalidPacket`.
+    pub fn l4_payload(&self) -> Result<&[u8], Error> {
+        l4_payload(self.payload_bytes()?)
+    }
+
+    /// Parse the `IPHeader` from the message
+    ///
+    /// When parsing `IPHeader` from a message, the `Queue`'s `CopyMode` and the `Handle` should be sized to the `IPHeader`.
+    /// The best way to do this is with the `queue_builder.set_copy_mode_sized_to_payload`
+    /// and `handle.start_sized_to_payload` methods.
+    /// See `examples/get_addrs.rs`.
+    ///
+    /// # Safety
+    ///
+    /// As for `payload`; `parse::<IPHeader>` is the safe alternative.
+    pub unsafe fn ip_header(&self) -> Result<&IPHeader, Error> {
+        self.payload::<IPHeader>()
+    }
+
+    /// Parse a sized `Payload` from the message
+    ///
+    /// The size of the `Payload` must be equal to the value that `handle.start` was called with.
+    /// If fewer bytes than the size of the `Payload` were copied to userspace, an `Error` is returned.
+    /// The best way to do this is with the `queue_builder.set_copy_mode_sized_to_payload`
+    /// and `handle.start_sized_to_payload` methods.
+    /// See `examples/get_addrs.rs`.
+    ///
+    /// # Safety
+    ///
+    /// The payload must be suitably aligned for `A`, and its bytes a valid `A`.
+    pub unsafe fn payload<A: Payload>(&self) -> Result<&A, Error> {
+        view(self.payload_bytes()?)
+    }
+
+    /// Parse a `FromBytes` payload from the message
+    ///
+    /// This is the safe counterpart of `payload`.
+    /// If too few bytes were copied to userspace, or they are misaligned for `A`, an `Error` is returned.
+    pub fn parse<A: FromBytes>(&self) -> Result<&A, Error> {
+        let bytes = self.payload_bytes()?;
+        if !(bytes.as_ptr() as usize).is_multiple_of(mem::align_of::<A>()) {
+            return Err(error(Reason::GetPayload, "Payload misaligned", None));
+        }
+        unsafe { view(bytes) }
+    }
+}
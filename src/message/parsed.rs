@@ -0,0 +1,140 @@
+//! An owned summary of a packet's L3 and L4 headers
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr};
+use error::*;
+use super::{view, ip_header_length, IP_OFFSET_MASK, Protocol, IPv6Header, TcpHeader, UdpHeader, IcmpHeader};
+
+const IPV4_MIN_HEADER_LENGTH: usize = 20;
+
+/// The L4 header of a `ParsedPacket`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// A TCP segment
+    Tcp {
+        /// The source port
+        sport: u16,
+        /// The destination port
+        dport: u16,
+        /// The TCP flags
+        flags: u8
+    },
+    /// A UDP datagram
+    Udp {
+        /// The source port
+        sport: u16,
+        /// The destination port
+        dport: u16
+    },
+    /// An ICMP message
+    Icmp {
+        /// The ICMP type
+        icmp_type: u8,
+        /// The ICMP code
+        code: u8
+    },
+    /// An L4 header which was not parsed, because the protocol is unknown,
+    /// the packet is a non-first fragment, or too little of the packet was copied
+    Other
+}
+
+/// A fully parsed, owned summary of an IPv4 or IPv6 packet
+///
+/// All fields are in host byte order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParsedPacket {
+    /// The source address
+    pub saddr: IpAddr,
+    /// The destination address
+    pub daddr: IpAddr,
+    /// The protocol of the L4 header
+    ///
+    /// For IPv6, this is the next header of the fixed header, so extension headers are not followed.
+    pub protocol: Protocol,
+    /// The length of the packet according to its IP header, which may exceed the bytes copied
+    pub length: usize,
+    /// The parsed L4 header
+    pub transport: Transport
+}
+
+impl ParsedPacket {
+    #[doc(hidden)]
+    pub fn parse(bytes: &[u8]) -> Result<ParsedPacket, Error> {
+        if bytes.is_empty() {
+            return Err(payload_too_short(0, IPV4_MIN_HEADER_LENGTH));
+        }
+        match bytes[0] >> 4 {
+            4 => ParsedPacket::parse_v4(bytes),
+            6 => ParsedPacket::parse_v6(bytes),
+            _ => Err(error(Reason::InvalidPacket, "Unknown IP version", None))
+        }
+    }
+
+    fn parse_v4(bytes: &[u8]) -> Result<ParsedPacket, Error> {
+        if bytes.len() < IPV4_MIN_HEADER_LENGTH {
+            return Err(payload_too_short(bytes.len(), IPV4_MIN_HEADER_LENGTH));
+        }
+        let offset = ip_header_length(bytes[0]);
+        if offset < IPV4_MIN_HEADER_LENGTH {
+            return Err(error(Reason::InvalidPacket, "Invalid IP header length", None));
+        }
+
+        let protocol = Protocol::from(bytes[9]);
+        // Only the first fragment carries the L4 header
+        let fragment_offset = ((bytes[6] as u16) << 8 | bytes[7] as u16) & IP_OFFSET_MASK;
+        let transport = if fragment_offset != 0 || bytes.len() < offset {
+            Transport::Other
+        } else {
+            transport(protocol, &bytes[offset..])
+        };
+
+        Ok(ParsedPacket {
+            saddr: IpAddr::V4(Ipv4Addr::new(bytes[12], bytes[13], bytes[14], bytes[15])),
+            daddr: IpAddr::V4(Ipv4Addr::new(bytes[16], bytes[17], bytes[18], bytes[19])),
+            protocol: protocol,
+            length: ((bytes[2] as usize) << 8) | bytes[3] as usize,
+            transport: transport
+        })
+    }
+
+    fn parse_v6(bytes: &[u8]) -> Result<ParsedPacket, Error> {
+        // `IPv6Header` is packed, so needs no alignment
+        let header = unsafe { view::<IPv6Header>(bytes)? };
+        let protocol = Protocol::from(header.next_header());
+        let offset = mem::size_of::<IPv6Header>();
+
+        Ok(ParsedPacket {
+            saddr: IpAddr::V6(header.saddr()),
+            daddr: IpAddr::V6(header.daddr()),
+            protocol: protocol,
+            length: header.payload_length() as usize + offset,
+            transport: transport(protocol, &bytes[offset..])
+        })
+    }
+
+    /// The source and destination ports, if the packet is TCP or UDP
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        match self.transport {
+            Transport::Tcp { sport, dport, .. } | Transport::Udp { sport, dport } => Some((sport, dport)),
+            _ => None
+        }
+    }
+}
+
+// Parse the L4 header at the start of `bytes`
+fn transport(protocol: Protocol, bytes: &[u8]) -> Transport {
+    // The L4 headers are packed, so need no alignment
+    unsafe {
+        match protocol {
+            Protocol::Tcp => view::<TcpHeader>(bytes).ok().map(|h| {
+                Transport::Tcp { sport: h.sport(), dport: h.dport(), flags: h.flags() }
+            }),
+            Protocol::Udp => view::<UdpHeader>(bytes).ok().map(|h| {
+                Transport::Udp { sport: h.sport(), dport: h.dport() }
+            }),
+            Protocol::Icmp => view::<IcmpHeader>(bytes).ok().map(|h| {
+                Transport::Icmp { icmp_type: h.icmp_type(), code: h.code() }
+            }),
+            Protocol::Unknown(_) => None
+        }.unwrap_or(Transport::Other)
+    }
+}
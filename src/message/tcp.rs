@@ -0,0 +1,211 @@
+//! TCP header parsing
+use std::cmp;
+use super::{Payload, FromBytes, IPHeader};
+use super::checksum;
+use super::sealed::Sealed;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_URG: u8 = 0x20;
+
+const TCP_FIXED_LENGTH: usize = 20;
+const TCP_OPTION_EOL: u8 = 0;
+const TCP_OPTION_NOP: u8 = 1;
+const TCP_OPTION_MSS: u8 = 2;
+const TCP_CHECKSUM_OFFSET: usize = 16;
+
+#[allow(dead_code)]
+#[allow(missing_docs)]
+#[repr(C, packed)]
+/// A `Payload` to fetch and parse a TCP header
+///
+/// Only the fixed 20 bytes of the header are included; options are not parsed.
+pub struct TcpHeader {
+    pub sport_raw: u16,
+    pub dport_raw: u16,
+    pub seq_raw: u32,
+    pub ack_raw: u32,
+    pub offset_and_flags_raw: u16,
+    pub window_raw: u16,
+    pub checksum_raw: u16,
+    pub urgent_pointer_raw: u16
+}
+
+impl TcpHeader {
+    /// Parse the source port
+    pub fn sport(&self) -> u16 {
+        u16::from_be(self.sport_raw)
+    }
+
+    /// Parse the destination port
+    pub fn dport(&self) -> u16 {
+        u16::from_be(self.dport_raw)
+    }
+
+    /// Parse the sequence number
+    pub fn seq(&self) -> u32 {
+        u32::from_be(self.seq_raw)
+    }
+
+    /// Parse the acknowledgment number
+    pub fn ack(&self) -> u32 {
+        u32::from_be(self.ack_raw)
+    }
+
+    /// Parse the data offset, in 32-bit words
+    ///
+    /// This is the length of the header including options.
+    pub fn data_offset(&self) -> u8 {
+        (u16::from_be(self.offset_and_flags_raw) >> 12) as u8
+    }
+
+    /// Parse the flags (`CWR`, `ECE`, `URG`, `ACK`, `PSH`, `RST`, `SYN`, `FIN`, from high bit to low)
+    pub fn flags(&self) -> u8 {
+        u16::from_be(self.offset_and_flags_raw) as u8
+    }
+
+    /// Parse the window size
+    pub fn window(&self) -> u16 {
+        u16::from_be(self.window_raw)
+    }
+
+    /// Parse the checksum
+    pub fn checksum(&self) -> u16 {
+        u16::from_be(self.checksum_raw)
+    }
+
+    /// Parse the urgent pointer
+    pub fn urgent_pointer(&self) -> u16 {
+        u16::from_be(self.urgent_pointer_raw)
+    }
+
+    /// Whether the `FIN` flag is set
+    pub fn is_fin(&self) -> bool { self.flags() & TCP_FIN != 0 }
+
+    /// Whether the `SYN` flag is set
+    pub fn is_syn(&self) -> bool { self.flags() & TCP_SYN != 0 }
+
+    /// Whether the `RST` flag is set
+    pub fn is_rst(&self) -> bool { self.flags() & TCP_RST != 0 }
+
+    /// Whether the `PSH` flag is set
+    pub fn is_psh(&self) -> bool { self.flags() & TCP_PSH != 0 }
+
+    /// Whether the `ACK` flag is set
+    pub fn is_ack(&self) -> bool { self.flags() & TCP_ACK != 0 }
+
+    /// Whether the `URG` flag is set
+    pub fn is_urg(&self) -> bool { self.flags() & TCP_URG != 0 }
+
+    /// Iterate over the options of this header, as `(kind, data)`
+    ///
+    /// `segment` is the segment this header was parsed from, starting at the header,
+    /// since only the fixed 20 bytes are part of `TcpHeader`.
+    /// The options are read up to the data offset, or the end of `segment` if it is cut short.
+    /// `NOP` options are yielded with no data; iteration stops at `EOL` or a malformed length.
+    pub fn options<'a>(&self, segment: &'a [u8]) -> TcpOptionsIter<'a> {
+        let end = cmp::min(self.data_offset() as usize * 4, segment.len());
+        let rest = if end > TCP_FIXED_LENGTH { &segment[TCP_FIXED_LENGTH..end] } else { &[] };
+        TcpOptionsIter { rest: rest }
+    }
+}
+
+/// An iterator over the options of a TCP header, created by `TcpHeader::options`
+pub struct TcpOptionsIter<'a> {
+    rest: &'a [u8]
+}
+
+impl<'a> Iterator for TcpOptionsIter<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let kind = match self.rest.first() {
+            None | Some(&TCP_OPTION_EOL) => return None,
+            Some(&kind) => kind
+        };
+        if kind == TCP_OPTION_NOP {
+            self.rest = &self.rest[1..];
+            return Some((kind, &[]));
+        }
+
+        // The length includes the kind and length bytes
+        let len = match self.rest.get(1) {
+            Some(&len) if len >= 2 && len as usize <= self.rest.len() => len as usize,
+            _ => {
+                self.rest = &[];
+                return None;
+            }
+        };
+        let data = &self.rest[2..len];
+        self.rest = &self.rest[len..];
+        Some((kind, data))
+    }
+}
+
+/// Lower the MSS option of a TCP segment to `max_mss`, if it exceeds it
+///
+/// `segment` starts at the TCP header, and is modified in place, so it should be a copy of the
+/// packet to be passed to `Verdict::set_verdict_payload`.
+/// The TCP checksum is updated incrementally, so the IP header is not needed.
+/// Returns whether the segment was changed; it is left alone if it has no MSS option.
+pub fn clamp_mss(segment: &mut [u8], max_mss: u16) -> bool {
+    if segment.len() < TCP_FIXED_LENGTH {
+        return false;
+    }
+    // Find the offset of the option's value within `segment`
+    let offset = {
+        let header = unsafe { &*(segment.as_ptr() as *const TcpHeader) };
+        let base = segment.as_ptr() as usize;
+        match header.options(segment).find(|&(kind, data)| kind == TCP_OPTION_MSS && data.len() == 2) {
+            Some((_, data)) => data.as_ptr() as usize - base,
+            None => return false
+        }
+    };
+    let mss = (segment[offset] as u16) << 8 | segment[offset + 1] as u16;
+    if mss <= max_mss {
+        return false;
+    }
+
+    // The value may straddle two of the 16-bit words the checksum is computed over
+    let first = offset & !1;
+    let last = (offset + 1) & !1;
+    let old = [word(segment, first), word(segment, last)];
+    segment[offset] = (max_mss >> 8) as u8;
+    segment[offset + 1] = max_mss as u8;
+
+    let mut sum = word(segment, TCP_CHECKSUM_OFFSET);
+    sum = checksum::update(sum, old[0], word(segment, first));
+    if last != first {
+        sum = checksum::update(sum, old[1], word(segment, last));
+    }
+    segment[TCP_CHECKSUM_OFFSET] = (sum >> 8) as u8;
+    segment[TCP_CHECKSUM_OFFSET + 1] = sum as u8;
+    true
+}
+
+// The big-endian 16-bit word at `offset`, padded with zero at the end of `data`
+fn word(data: &[u8], offset: usize) -> u16 {
+    let low = data.get(offset + 1).cloned().unwrap_or(0);
+    (data[offset] as u16) << 8 | low as u16
+}
+
+impl Payload for TcpHeader {}
+impl Sealed for TcpHeader {}
+impl FromBytes for TcpHeader {}
+
+#[allow(missing_docs)]
+#[repr(C)]
+/// A `Payload` to fetch and parse both the IP and TCP headers of a packet
+///
+/// This assumes the IP header carries no options; `Message::l4` handles packets with options.
+pub struct IPTcpHeader {
+    pub ip: IPHeader,
+    pub tcp: TcpHeader
+}
+
+impl Payload for IPTcpHeader {}
+impl Sealed for IPTcpHeader {}
+impl FromBytes for IPTcpHeader {}
@@ -0,0 +1,56 @@
+//! UDP header parsing
+use super::{Payload, FromBytes, IPHeader};
+use super::sealed::Sealed;
+
+#[allow(dead_code)]
+#[allow(missing_docs)]
+#[repr(C, packed)]
+/// A `Payload` to fetch and parse a UDP header
+pub struct UdpHeader {
+    pub sport_raw: u16,
+    pub dport_raw: u16,
+    pub length_raw: u16,
+    pub checksum_raw: u16
+}
+
+impl UdpHeader {
+    /// Parse the source port
+    pub fn sport(&self) -> u16 {
+        u16::from_be(self.sport_raw)
+    }
+
+    /// Parse the destination port
+    pub fn dport(&self) -> u16 {
+        u16::from_be(self.dport_raw)
+    }
+
+    /// Parse the length of the datagram, including this header, in bytes
+    pub fn length(&self) -> u16 {
+        u16::from_be(self.length_raw)
+    }
+
+    /// Parse the checksum
+    ///
+    /// A checksum of 0 means that none was computed by the sender.
+    pub fn checksum(&self) -> u16 {
+        u16::from_be(self.checksum_raw)
+    }
+}
+
+impl Payload for UdpHeader {}
+impl Sealed for UdpHeader {}
+impl FromBytes for UdpHeader {}
+
+#[allow(missing_docs)]
+#[repr(C)]
+/// A `Payload` to fetch and parse both the IP and UDP headers of a packet
+///
+/// This assumes the IP header carries no options; `Message::l4` handles packets with options.
+pub struct IPUdpHeader {
+    pub ip: IPHeader,
+    pub udp: UdpHeader
+}
+
+impl Payload for IPUdpHeader {}
+impl Sealed for IPUdpHeader {}
+impl FromBytes for IPUdpHeader {}
@@ -0,0 +1,56 @@
+//! 802.1Q VLAN tags
+//!
+//! The tag is carried in the nested attributes of `NFQA_VLAN`.
+use super::find_attribute;
+
+const NFQA_VLAN_PROTO: u16 = 1;
+const NFQA_VLAN_TCI: u16 = 2;
+
+/// The 802.1Q VLAN tag of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VlanTag {
+    tpid: u16,
+    tci: u16
+}
+
+impl VlanTag {
+    #[doc(hidden)]
+    pub fn parse(raw: &[u8]) -> Option<VlanTag> {
+        let tpid = find_attribute(raw, NFQA_VLAN_PROTO).and_then(be16)?;
+        let tci = find_attribute(raw, NFQA_VLAN_TCI).and_then(be16)?;
+        Some(VlanTag { tpid: tpid, tci: tci })
+    }
+
+    /// The Tag Protocol Identifier, e.g. `0x8100` for 802.1Q or `0x88a8` for 802.1ad
+    pub fn tpid(&self) -> u16 {
+        self.tpid
+    }
+
+    /// The Tag Control Information, holding the priority, DEI and VLAN id
+    pub fn tci(&self) -> u16 {
+        self.tci
+    }
+
+    /// The 12-bit VLAN identifier
+    pub fn vid(&self) -> u16 {
+        self.tci & 0x0fff
+    }
+
+    /// The 3-bit Priority Code Point
+    pub fn pcp(&self) -> u8 {
+        (self.tci >> 13) as u8
+    }
+
+    /// The Drop Eligible Indicator
+    pub fn dei(&self) -> bool {
+        self.tci & 0x1000 != 0
+    }
+}
+
+fn be16(raw: &[u8]) -> Option<u16> {
+    if raw.len() < 2 {
+        None
+    } else {
+        Some((raw[0] as u16) << 8 | raw[1] as u16)
+    }
+}
@@ -0,0 +1,68 @@
+//! Fluent configuration for NFQueue queues.
+use error::*;
+use handle::Handle;
+use super::{Queue, CopyMode, QueueFlags, PacketHandler};
+
+/// A builder to create and configure a `Queue` in one step
+///
+/// Settings are collected, then applied by `build`.
+/// If any setting fails to apply, the queue is destroyed before the error is returned.
+pub struct QueueBuilder {
+    copy_mode: Option<CopyMode>,
+    max_length: Option<u32>,
+    flags: Option<QueueFlags>
+}
+
+impl QueueBuilder {
+    /// Create a builder which applies no settings
+    pub fn new() -> QueueBuilder {
+        QueueBuilder {
+            copy_mode: None,
+            max_length: None,
+            flags: None
+        }
+    }
+
+    /// Set the copy-mode of the queue
+    pub fn copy_mode(mut self, mode: CopyMode) -> QueueBuilder {
+        self.copy_mode = Some(mode);
+        self
+    }
+
+    /// Set the max-length of the queue
+    pub fn max_length(mut self, length: u32) -> QueueBuilder {
+        self.max_length = Some(length);
+        self
+    }
+
+    /// Enable the given flags on the queue
+    pub fn flags(mut self, flags: QueueFlags) -> QueueBuilder {
+        self.flags = Some(flags);
+        self
+    }
+
+    /// Create the queue on `handle` and apply the collected settings
+    pub fn build<F: PacketHandler>(self,
+                                   handle: &mut Handle,
+                                   queue_number: u16,
+                                   handler: F) -> Result<Box<Queue<F>>, Error> {
+        // On error, dropping `queue` destroys it
+        let mut queue = handle.queue(queue_number, handler)?;
+        if let Some(mode) = self.copy_mode {
+            queue.set_mode(mode)?;
+        }
+        if let Some(length) = self.max_length {
+            queue.set_max_length(length)?;
+        }
+        if let Some(flags) = self.flags {
+            queue.set_flags(flags, flags)?;
+        }
+        Ok(queue)
+    }
+}
+
+impl Default for QueueBuilder {
+    fn default() -> QueueBuilder {
+        QueueBuilder::new()
+    }
+}
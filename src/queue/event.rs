@@ -0,0 +1,62 @@
+//! Pull-based packet handling, for use with `Handle::packets`.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use error::*;
+use message::Message;
+use super::{PacketHandler, PendingVerdict, QueueHandle};
+
+#[doc(hidden)]
+pub type EventBuffer = Rc<RefCell<VecDeque<Result<PacketEvent, Error>>>>;
+
+/// A packet received from a queue
+///
+/// Every event must be resolved with its `verdict`.
+/// Dropping an event without setting a verdict leaves the packet in the kernel's queue.
+pub struct PacketEvent {
+    /// The packet id
+    pub id: u32,
+    /// The HW protocol of the packet
+    pub hw_protocol: u16,
+    /// The netfilter hook the packet was queued from
+    pub hook: u8,
+    /// The bytes of the packet copied to userspace
+    ///
+    /// This is an `Error` if the payload cannot be read, e.g. because the queue's `CopyMode`
+    /// is not `Packet`; the packet still awaits its `verdict`.
+    pub payload: Result<Vec<u8>, Error>,
+    /// Sets the verdict for the packet
+    pub verdict: PendingVerdict
+}
+
+/// A `PacketHandler` which buffers packets as `PacketEvent`s
+///
+/// Queues using this handler are created with `Handle::event_queue`.
+/// Every packet is buffered with its `PendingVerdict`, even if its payload cannot be read.
+/// Only a message without a packet header is buffered as an error: it has no packet id,
+/// so there is no packet to set a verdict for.
+pub struct EventHandler { events: EventBuffer }
+
+impl EventHandler {
+    #[doc(hidden)]
+    pub fn new(events: EventBuffer) -> EventHandler {
+        EventHandler { events: events }
+    }
+}
+
+impl PacketHandler for EventHandler {
+    fn handle(&mut self, qh: QueueHandle, message: Result<&Message, &Error>) -> i32 {
+        let event = match message {
+            Ok(m) => Ok(PacketEvent {
+                id: m.header.id(),
+                hw_protocol: m.header.hw_protocol(),
+                hook: m.header.hook(),
+                payload: m.payload_bytes().map(|payload| payload.to_vec()),
+                verdict: PendingVerdict::new(qh, m.header.id())
+            }),
+            Err(_) => Err(error(Reason::GetHeader, "Failed to get header", None))
+        };
+        self.events.borrow_mut().push_back(event);
+        0
+    }
+}
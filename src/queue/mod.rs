@@ -3,134 +3,392 @@
 //! The queue handle and callback,
 //! analagous to <http://netfilter.org/projects/libnetfilter_queue/doxygen/group__Queue.html>
 mod verdict;
+mod builder;
+mod event;
+mod stats;
 
+use errno::errno;
 use libc::*;
+use std::any::Any;
+use std::cmp;
+use std::marker::PhantomData;
 use std::mem;
-use std::ptr::null;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::ptr::null_mut;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 
 use error::*;
 use util::*;
 use message::{Message, Payload};
-pub use self::verdict::Verdict;
+pub use self::verdict::{Verdict, QueueHandle, PendingVerdict};
+pub use self::builder::QueueBuilder;
+pub use self::event::{PacketEvent, EventHandler, EventBuffer};
+pub use self::stats::{QueueStats, KernelQueueStats, kernel_stats};
+#[doc(hidden)]
+pub use self::stats::parse_kernel_stats;
+use self::stats::QueueCounters;
+use self::verdict::QueueState;
+use handle::HandleState;
 use lock::NFQ_LOCK as LOCK;
 
 use ffi::*;
-pub use ffi::nfq_q_handle as QueueHandle;
+pub use ffi::{nfq_data, nfgenmsg};
 
 const NFQNL_COPY_NONE: uint8_t = 0;
 const NFQNL_COPY_META: uint8_t = 1;
 const NFQNL_COPY_PACKET: uint8_t = 2;
 
+// The largest copy range, which covers any IP packet
+const MAX_COPY_RANGE: u16 = 0xffff;
+
+// The length of an IPv4 header without options
+const IPV4_HEADER_LENGTH: u16 = 20;
+
+bitflags! {
+    /// Flags controlling the behavior of a queue
+    pub struct QueueFlags: u32 {
+        /// Accept packets instead of dropping them when the queue is full
+        const FAIL_OPEN = 0x01;
+        /// Attach conntrack information to packets
+        const CONNTRACK = 0x02;
+        /// Deliver GSO packets whole rather than segmenting them
+        const GSO = 0x04;
+        /// Attach the uid and gid of the owning socket to locally-generated packets
+        const UID_GID = 0x08;
+        /// Attach the security context to packets
+        const SECCTX = 0x10;
+    }
+}
+
+/// How a queue handles messages which fail to parse into a `Message`
+///
+/// This applies to messages without a packet header, which cannot be given a verdict.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseErrorPolicy {
+    /// Skip the message without passing it to the handler (the default)
+    #[default]
+    SkipAndContinue,
+    /// Skip the message, counting it in the queue's `QueueStats::errors`
+    SkipAndCount,
+    /// Pass the error to the handler, counting it in the queue's `QueueStats::errors`
+    Deliver,
+    /// Abort the process
+    Abort
+}
+
 /// The amount of data to be copied to userspace for each packet queued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CopyMode {
     /// None
     None,
     /// Packet metadata only
     Metadata,
-    /// If you copy the packet, you must also specify the size of the packet to copy
+    /// If you copy the packet, you must also specify the size of the packet to copy, in bytes
     Packet(u16)
 }
 
+impl CopyMode {
+    // The mode and range to pass to `nfq_set_mode`
+    fn as_raw(&self) -> (uint8_t, uint32_t) {
+        match *self {
+            CopyMode::None => (NFQNL_COPY_NONE, 0),
+            CopyMode::Metadata => (NFQNL_COPY_META, 0),
+            CopyMode::Packet(range) => (NFQNL_COPY_PACKET, range as uint32_t)
+        }
+    }
+}
 
 
-extern fn queue_callback<F: PacketHandler>(qh: *mut QueueHandle,
+extern "C" fn queue_callback<F: PacketHandler>(qh: *mut nfq_q_handle,
                                            nfmsg: *mut nfgenmsg,
                                            nfad: *mut nfq_data,
                                            cdata: *mut c_void) -> c_int {
-    let queue_ptr: *mut Queue<F> = unsafe { mem::transmute(cdata) };
+    let queue_ptr = cdata as *mut Queue<F>;
     let queue: &mut Queue<F> = unsafe { as_mut(&queue_ptr).unwrap() };
-    let message = Message::new(nfmsg, nfad);
+    // Each queue registers itself as its own callback data, so the shared callback
+    // only ever sees the packets of the queue it was handed.
+    debug_assert_eq!(qh, queue.ptr());
+    let message = unsafe { Message::new(nfmsg, nfad) };
 
-    queue.callback.handle(qh, message.as_ref()) as c_int
+    QueueCounters::increment(&queue.state.stats.received);
+    match message {
+        Ok(ref m) => queue.last_id = Some(m.header.id()),
+        Err(ref e) => match queue.parse_error_policy {
+            ParseErrorPolicy::SkipAndContinue => {
+                debug!("Skipping malformed message on queue {}: {}", queue.number, e);
+                return 0;
+            },
+            ParseErrorPolicy::SkipAndCount => {
+                QueueCounters::increment(&queue.state.stats.errors);
+                return 0;
+            },
+            ParseErrorPolicy::Deliver => QueueCounters::increment(&queue.state.stats.errors),
+            ParseErrorPolicy::Abort => {
+                error!("Aborting on malformed message on queue {}: {}", queue.number, e);
+                process::abort();
+            }
+        }
+    }
+    let hq = QueueHandle::new(queue.state.clone());
+
+    // Unwinding into the C caller is undefined behavior, so a panicking handler is caught,
+    // and its packet is left without a verdict
+    let callback = &mut queue.callback;
+    match panic::catch_unwind(AssertUnwindSafe(|| callback.handle(hq, message.as_ref()))) {
+        Ok(res) => res as c_int,
+        Err(payload) => {
+            error!("Packet handler of queue {} panicked: {}", queue.number, panic_message(&*payload));
+            QueueCounters::increment(&queue.state.stats.errors);
+            if queue.panic.is_none() {
+                queue.panic = Some(payload);
+            }
+            0
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => s,
+        None => payload.downcast_ref::<String>().map(|s| &s[..]).unwrap_or("Box<Any>")
+    }
 }
 
 /// A handle to an NFQueue queue
 ///
 /// This is used to set queue-specific settings, such as copy-mode and max-length.
+/// Several queues, each with its own handler, may be created on a single `Handle`.
 pub struct Queue<F: PacketHandler> {
-    ptr: *mut QueueHandle,
-    callback: F
+    // Shared with the queue's `QueueHandle`s, which may outlive it
+    state: Arc<QueueState>,
+    number: u16,
+    panic: Option<Box<dyn Any + Send>>,
+    // The id of the last packet passed to the handler
+    last_id: Option<u32>,
+    parse_error_policy: ParseErrorPolicy,
+    callback: F,
+    // The queue is registered with its handle, so must be used and dropped on the handle's thread
+    _not_send: PhantomData<*mut nfq_q_handle>
 }
 
 impl<F: PacketHandler> Drop for Queue<F> {
     fn drop(&mut self) {
-        let ret = unsafe { nfq_destroy_queue(self.ptr) };
-        if ret != 0 {
-            panic!("Failed to destroy nfq queue");
+        // Panicking here would abort if already unwinding, so failures are only logged
+        if let Err(e) = self.destroy() {
+            error!("Failed to destroy queue {}: {}", self.number, e);
         }
     }
 }
 
 impl<F: PacketHandler> Queue<F> {
-    #[doc(hidden)]
-    pub fn new(handle: *mut nfq_handle,
-               queue_number: uint16_t,
-               packet_handler: F) -> Result<Box<Queue<F>>, Error> {
+    // `bound` is whether the handle has bound a protocol family, which needs `CAP_NET_ADMIN`
+    pub(crate) fn new(handle: Arc<HandleState>,
+                      bound: bool,
+                      queue_number: uint16_t,
+                      packet_handler: F) -> Result<Box<Queue<F>>, Error> {
         let _lock = LOCK.lock().unwrap();
+        let handle_ptr = handle.ptr;
 
-        let nfq_ptr: *const QueueHandle = null();
         let mut queue: Box<Queue<F>> = Box::new(Queue {
-            ptr: nfq_ptr as *mut QueueHandle, // set after nfq_create_queue
+            state: Arc::new(QueueState::new(handle)), // the pointer is set after nfq_create_queue
+            number: queue_number,
+            panic: None,
+            last_id: None,
+            parse_error_policy: ParseErrorPolicy::default(),
             callback: packet_handler,
+            _not_send: PhantomData
         });
         let queue_ptr: *mut Queue<F> = &mut *queue;
 
         let ptr = unsafe {
-            nfq_create_queue(handle,
+            nfq_create_queue(handle_ptr,
                              queue_number,
                              queue_callback::<F>,
-                             mem::transmute(queue_ptr))
+                             queue_ptr as *mut c_void)
         };
 
         if ptr.is_null() {
-            return Err(error(Reason::CreateQueue, "Failed to create queue", None));
+            let err = errno();
+            // The kernel refuses a queue number bound by another socket with `EPERM`, as it refuses
+            // a process lacking `CAP_NET_ADMIN`. Binding a family proves the capability,
+            // so only then is `EPERM` known to mean the queue is taken.
+            return Err(match err.0 {
+                EPERM if bound => error_with_errno(Reason::QueueBusy,
+                                                   &format!("Queue {} is already in use by another process",
+                                                            queue_number),
+                                                   None, err),
+                EPERM => error_with_errno(Reason::PermissionDenied,
+                                          &format!("Failed to create queue {}: CAP_NET_ADMIN is required, \
+                                                    or the queue is in use by another process", queue_number),
+                                          None, err),
+                _ => subsystem_error(Reason::CreateQueue, "Failed to create queue", None, err)
+            });
         } else {
-            queue.ptr = ptr;
+            queue.state.ptr.store(ptr, Ordering::Relaxed);
         }
 
         Ok(queue)
     }
 
-    /// Set the copy-mode for this queue
-    pub fn set_mode(&mut self, mode: CopyMode) -> Result<(), Error> {
-        let copy_mode = match mode {
-            CopyMode::None => NFQNL_COPY_NONE,
-            CopyMode::Metadata => NFQNL_COPY_META,
-            CopyMode::Packet(_) => NFQNL_COPY_PACKET
-        } as uint8_t;
-        let range = match mode {
-            CopyMode::Packet(r) => r,
-            _ => 0
-        } as uint32_t;
-
-        let res = unsafe { nfq_set_mode(self.ptr, copy_mode, range) };
+    /// Destroy the queue, unbinding it from its queue number
+    ///
+    /// This is done when the queue is dropped, but only this reports whether it failed.
+    // The queue is registered with the library by its address, so is closed in its box
+    #[allow(clippy::boxed_local)]
+    pub fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.destroy()
+    }
+
+    // Destroy the queue, at most once
+    fn destroy(&mut self) -> Result<(), Error> {
+        // Only the queue itself clears the pointer, so this needs no lock;
+        // `new` fails with the lock held, and the pointer still null
+        if self.ptr().is_null() {
+            return Ok(());
+        }
+        let _lock = LOCK.lock().unwrap();
+        let ptr = self.state.ptr.swap(null_mut(), Ordering::Relaxed);
+        let res = unsafe { nfq_destroy_queue(ptr) };
         if res != 0 {
-            Err(error(Reason::SetQueueMode, "Failed to set queue mode", Some(res)))
+            Err(error(Reason::DestroyQueue, "Failed to destroy queue", Some(res)))
         } else {
             Ok(())
         }
     }
 
+    // The library's queue, or null once destroyed
+    fn ptr(&self) -> *mut nfq_q_handle {
+        self.state.ptr.load(Ordering::Relaxed)
+    }
+
+    /// Get the number this queue is bound to
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    /// Get a `QueueHandle` to set the verdicts of this queue's packets
+    pub fn handle(&self) -> QueueHandle {
+        QueueHandle::new(self.state.clone())
+    }
+
+    /// Get the counts of packets handled by this queue
+    ///
+    /// Messages are counted as they are received, and packets as their verdicts are set
+    /// through a `QueueHandle` of this queue. Batch verdicts are only counted if they fail.
+    pub fn stats(&self) -> QueueStats {
+        self.state.stats.snapshot()
+    }
+
+    /// Accept every packet of this queue still awaiting a verdict
+    ///
+    /// This sets a batch verdict up to the last packet passed to the handler, so that no packets
+    /// are left stalled in the kernel, e.g. when shutting down before dropping the queue.
+    /// The verdict is sent without waiting for the kernel, so this only fails if it cannot be sent.
+    /// If every packet already has a verdict, the kernel rejects it with `ENOENT`, which arrives as
+    /// an error message on the socket: the next receive of a dispatch loop reports it to
+    /// `Handle::on_packet_error`.
+    pub fn accept_all_pending(&mut self) -> Result<(), Error> {
+        match self.last_id {
+            Some(id) => Verdict::set_verdict_batch(&self.handle(), id, Verdict::Accept).map(|_| ()),
+            None => Ok(())
+        }
+    }
+
+    /// Set how messages which fail to parse are handled
+    pub fn set_parse_error_policy(&mut self, policy: ParseErrorPolicy) {
+        self.parse_error_policy = policy;
+    }
+
+    /// Take the payload of the first panic caught from this queue's handler, if any
+    ///
+    /// A panicking handler does not stop the dispatch loop, and its packet is left without a verdict.
+    /// The payload may be re-raised with `std::panic::resume_unwind`.
+    pub fn take_panic(&mut self) -> Option<Box<dyn Any + Send>> {
+        self.panic.take()
+    }
+
+    /// Set the copy-mode for this queue
+    ///
+    /// This may be called again while packets are being handled, e.g. to copy whole packets only
+    /// while a flow is of interest; see `QueueHandle::set_mode` to do so from within a handler.
+    /// The kernel copies each packet as it is queued, so a new mode applies only to packets
+    /// queued afterwards, not to those already waiting in the queue.
+    pub fn set_mode(&mut self, mode: CopyMode) -> Result<(), Error> {
+        self.handle().set_mode(mode)
+    }
+
+    /// Get the copy-mode last set on this queue
+    ///
+    /// This includes modes set through a `QueueHandle` of this queue.
+    /// The library cannot read the mode back from the kernel, so this is tracked as it is set;
+    /// a new queue copies nothing, so starts as `CopyMode::None`.
+    pub fn copy_mode(&self) -> CopyMode {
+        *self.state.copy_mode.lock().unwrap()
+    }
+
     /// Set the copy-mode to Packet for the size of the given struct
     ///
     /// This fn behaves like `set_mode` except that packet size is determined by the size of the type, `P`.
     /// For example, to copy enough to parse `IPHeader`, use `set_mode_sized::<IPHeader>()`.
     pub fn set_mode_sized<P: Payload>(&mut self) -> Result<(), Error> {
         let bytes = mem::size_of::<P>() as u16;
-        self.set_mode(CopyMode::Packet(bytes * 8))
+        self.set_mode(CopyMode::Packet(bytes))
+    }
+
+    /// Set the copy-mode to Packet for an IPv4 header followed by the given L4 struct
+    ///
+    /// Payloads begin at the L3 header, so an L4 struct such as `TcpHeader` is only copied in full
+    /// if the IP header is copied too. This assumes a 20-byte IPv4 header without options;
+    /// see `set_mode_sized_with_l3_length` for other L3 headers.
+    pub fn set_mode_sized_with_l3<P: Payload>(&mut self) -> Result<(), Error> {
+        self.set_mode_sized_with_l3_length::<P>(IPV4_HEADER_LENGTH)
+    }
+
+    /// Set the copy-mode to Packet for an L3 header of `l3_length` bytes followed by the given L4 struct
+    ///
+    /// IPv4 options lengthen the header to as much as 60 bytes, which pushes the L4 header later.
+    /// If options are expected, pass 60, or the L4 header of such packets will be truncated.
+    /// For IPv6 without extension headers, pass 40.
+    pub fn set_mode_sized_with_l3_length<P: Payload>(&mut self, l3_length: u16) -> Result<(), Error> {
+        let bytes = l3_length as usize + mem::size_of::<P>();
+        self.set_mode(CopyMode::Packet(cmp::min(bytes, MAX_COPY_RANGE as usize) as u16))
+    }
+
+    /// Set the copy-mode to Packet for the largest possible packet
+    ///
+    /// The entire packet is copied to userspace, and can be read with `message.payload_bytes`.
+    /// `0xffff` is the maximum length of an IP packet, so no packet is truncated,
+    /// at the cost of more copying and higher CPU usage than copying only the headers needed.
+    /// The `Handle`'s read buffer must also be large enough, which the default is.
+    pub fn set_mode_full(&mut self) -> Result<(), Error> {
+        self.set_mode(CopyMode::Packet(MAX_COPY_RANGE))
     }
 
     /// Set the max-length for this queue
     ///
     /// Once `length` packets are enqueued, packets will be dropped until enqueued packets are processed.
     pub fn set_max_length(&mut self, length: u32) -> Result<(), Error> {
-        let res = unsafe { nfq_set_queue_maxlen(self.ptr, length) };
+        let res = unsafe { nfq_set_queue_maxlen(self.ptr(), length) };
         if res != 0 {
             Err(error(Reason::SetQueueMaxlen, "Failed to set queue maxlen", Some(res)))
         } else {
             Ok(())
         }
     }
+
+    /// Set the flags for this queue
+    ///
+    /// Only flags included in `mask` are changed; each is enabled if it is also included in `flags`.
+    pub fn set_flags(&mut self, mask: QueueFlags, flags: QueueFlags) -> Result<(), Error> {
+        let res = unsafe { nfq_set_queue_flags(self.ptr(), mask.bits(), flags.bits()) };
+        if res != 0 {
+            Err(error(Reason::SetQueueFlags, "Failed to set queue flags", Some(res)))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 /// Invoked to handle packets from the queue
@@ -138,7 +396,119 @@ pub trait PacketHandler {
     /// Handle a packet from the queue
     ///
     /// `Verdict`s must be set using the `set_verdict` fn.
-    fn handle(&mut self, hq: *mut QueueHandle, message: Result<&Message, &Error>) -> i32;
+    fn handle(&mut self, hq: QueueHandle, message: Result<&Message, &Error>) -> i32;
+}
+
+/// Invoked with the unparsed pointers of each packet from the queue
+///
+/// This is an escape hatch for attributes which are not wrapped by `Message`,
+/// to be read with the `nfq_get_*` functions of libnetfilter_queue.
+/// The pointers are only valid for the duration of the call, and must not be retained;
+/// dereferencing them is up to the implementor, and is unsafe.
+/// Queues use a `RawHandler` by wrapping it in `Raw`.
+pub trait RawHandler {
+    /// Handle a packet from the queue
+    ///
+    /// `Verdict`s must be set using the `set_verdict` fn.
+    fn handle(&mut self, hq: QueueHandle, data: *mut nfq_data, msg: *mut nfgenmsg) -> i32;
+}
+
+/// A `PacketHandler` passing the unparsed pointers of each packet to a `RawHandler`
+///
+/// Messages without a packet header, which carry no packet, are not passed on.
+pub struct Raw<H: RawHandler>(pub H);
+
+impl<H: RawHandler> PacketHandler for Raw<H> {
+    fn handle(&mut self, hq: QueueHandle, message: Result<&Message, &Error>) -> i32 {
+        match message {
+            Ok(m) => self.0.handle(hq, m.ptr, m.raw),
+            Err(_) => 0
+        }
+    }
+}
+
+/// A value returned by a handler closure, standing for the `i32` returned by `PacketHandler::handle`
+///
+/// `()` and `true` continue, as `0` does. `false` fails the packet, as a negative value does,
+/// which is reported to the `Handle`'s `on_packet_error` callback and triggers `FailOpen`/`FailClosed`.
+pub trait HandlerResult {
+    /// Convert to the `i32` returned by `PacketHandler::handle`
+    fn into_status(self) -> i32;
+}
+
+impl HandlerResult for i32 {
+    fn into_status(self) -> i32 {
+        self
+    }
+}
+
+impl HandlerResult for () {
+    fn into_status(self) -> i32 {
+        0
+    }
+}
+
+impl HandlerResult for bool {
+    fn into_status(self) -> i32 {
+        if self { 0 } else { -1 }
+    }
+}
+
+/// A `PacketHandler` calling a closure with the `QueueHandle` and message of each packet
+///
+/// Queues using this handler are created with `Handle::queue_fn`.
+/// The closure may return any `HandlerResult`.
+pub struct FnHandler<F>(pub F);
+
+impl<F, R> PacketHandler for FnHandler<F>
+    where F: FnMut(QueueHandle, Result<&Message, &Error>) -> R, R: HandlerResult {
+    fn handle(&mut self, hq: QueueHandle, message: Result<&Message, &Error>) -> i32 {
+        (self.0)(hq, message).into_status()
+    }
+}
+
+/// A `PacketHandler` which accepts a packet if the wrapped handler fails on it
+///
+/// The handler fails if it returns a negative value or panics; the panic is then resumed,
+/// so it is still caught and recorded by the `Queue`.
+/// A handler which sets a verdict before failing is harmless, as the second verdict is rejected.
+pub struct FailOpen<H: PacketHandler>(pub H);
+
+impl<H: PacketHandler> PacketHandler for FailOpen<H> {
+    fn handle(&mut self, hq: QueueHandle, message: Result<&Message, &Error>) -> i32 {
+        handle_or(&mut self.0, hq, message, Verdict::Accept)
+    }
+}
+
+/// A `PacketHandler` which drops a packet if the wrapped handler fails on it
+///
+/// This is the counterpart of `FailOpen`, for handlers which must not let unvetted packets through.
+pub struct FailClosed<H: PacketHandler>(pub H);
+
+impl<H: PacketHandler> PacketHandler for FailClosed<H> {
+    fn handle(&mut self, hq: QueueHandle, message: Result<&Message, &Error>) -> i32 {
+        handle_or(&mut self.0, hq, message, Verdict::Drop)
+    }
+}
+
+// Pass the message to `handler`, setting `fallback` as the verdict if it fails
+fn handle_or<H: PacketHandler>(handler: &mut H, hq: QueueHandle, message: Result<&Message, &Error>,
+                               fallback: Verdict) -> i32 {
+    let id = match message {
+        Ok(m) => m.header.id(),
+        Err(_) => return handler.handle(hq, message)
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| handler.handle(hq.clone(), message))) {
+        Ok(res) if res >= 0 => res,
+        Ok(res) => {
+            let _ = Verdict::set_verdict_payload(&hq, id, fallback, &[]);
+            res
+        },
+        Err(payload) => {
+            let _ = Verdict::set_verdict_payload(&hq, id, fallback, &[]);
+            panic::resume_unwind(payload)
+        }
+    }
 }
 
 /// An abstraction over `PacketHandler` for simple handling that needs only a `Verdict`
@@ -149,13 +519,10 @@ pub trait VerdictHandler {
     fn decide(&mut self, message: &Message) -> Verdict;
 }
 
-#[allow(non_snake_case)]
 impl<V> PacketHandler for V where V: VerdictHandler {
-    fn handle(&mut self, hq: *mut QueueHandle, message: Result<&Message, &Error>) -> i32 {
-        let NULL: *const c_uchar = null();
-        match message {
-            Ok(m) => { let _ = Verdict::set_verdict(hq, m.header.id(), self.decide(m), 0, NULL); },
-            Err(_) => ()
+    fn handle(&mut self, hq: QueueHandle, message: Result<&Message, &Error>) -> i32 {
+        if let Ok(m) = message {
+            let _ = Verdict::set_verdict_payload(&hq, m.header.id(), self.decide(m), &[]);
         }
         0
     }
@@ -0,0 +1,118 @@
+//! Counters of the packets handled by a queue.
+use errno::Errno;
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use error::*;
+
+const KERNEL_STATS_PATH: &str = "/proc/net/netfilter/nfnetlink_queue";
+
+/// A snapshot of the packets handled by a `Queue`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Messages received for the queue, including any which failed to parse
+    pub received: u64,
+    /// Packets given an `Accept` or `Stop` verdict
+    pub accepted: u64,
+    /// Packets given a `Drop` verdict
+    pub dropped: u64,
+    /// Messages which could not be parsed (depending on the `ParseErrorPolicy`),
+    /// and verdicts which could not be set
+    pub errors: u64
+}
+
+#[doc(hidden)]
+#[derive(Default)]
+pub struct QueueCounters {
+    pub received: AtomicU64,
+    pub accepted: AtomicU64,
+    pub dropped: AtomicU64,
+    pub errors: AtomicU64
+}
+
+impl QueueCounters {
+    #[doc(hidden)]
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[doc(hidden)]
+    pub fn snapshot(&self) -> QueueStats {
+        QueueStats {
+            received: self.received.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// The kernel's counters for a queue, from `/proc/net/netfilter/nfnetlink_queue`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KernelQueueStats {
+    /// The queue number
+    pub queue_num: u16,
+    /// The netlink port id of the process bound to the queue
+    pub peer_portid: u32,
+    /// Packets currently waiting in the queue for a verdict
+    pub queue_total: u32,
+    /// The copy mode of the queue (0 for none, 1 for metadata, 2 for packet)
+    pub copy_mode: u8,
+    /// The number of bytes of each packet copied to userspace
+    pub copy_range: u32,
+    /// Packets dropped because the queue was full
+    pub queue_dropped: u32,
+    /// Packets dropped because they could not be sent to userspace, e.g. on `ENOBUFS`
+    pub user_dropped: u32,
+    /// The id of the last packet queued
+    pub id_sequence: u32
+}
+
+impl KernelQueueStats {
+    /// The number of packets dropped on their way to userspace since the snapshot `prev`
+    ///
+    /// This counts `user_dropped`, which is where `ENOBUFS` drops are recorded.
+    /// The kernel's counters are 32-bit, so a counter which has wrapped is still counted correctly.
+    pub fn user_dropped_since(&self, prev: &KernelQueueStats) -> u32 {
+        self.user_dropped.wrapping_sub(prev.user_dropped)
+    }
+}
+
+/// Read the kernel's counters for the queue `queue_num`
+///
+/// An `Error` is returned if the file cannot be read, which is the case when
+/// the `nfnetlink_queue` module is not loaded, or if no process is bound to the queue.
+pub fn kernel_stats(queue_num: u16) -> Result<KernelQueueStats, Error> {
+    let mut contents = String::new();
+    let read = File::open(KERNEL_STATS_PATH).and_then(|mut f| f.read_to_string(&mut contents));
+    if let Err(e) = read {
+        return Err(error_with_errno(Reason::ReadKernelStats, "Failed to read kernel queue stats",
+                                    None, Errno(e.raw_os_error().unwrap_or(0))));
+    }
+
+    parse_kernel_stats(&contents, queue_num)
+        .ok_or_else(|| error_with_errno(Reason::ReadKernelStats, "Queue not found in kernel queue stats",
+                                        None, Errno(0)))
+}
+
+// Each line is formatted by the kernel as
+// `queue_num peer_portid queue_total copy_mode copy_range queue_dropped user_dropped id_sequence 1`
+#[doc(hidden)]
+pub fn parse_kernel_stats(contents: &str, queue_num: u16) -> Option<KernelQueueStats> {
+    contents.lines().filter_map(|line| {
+        let fields: Vec<u32> = line.split_whitespace().map(|f| f.parse().ok()).collect::<Option<_>>()?;
+        if fields.len() < 8 {
+            return None;
+        }
+        Some(KernelQueueStats {
+            queue_num: fields[0] as u16,
+            peer_portid: fields[1],
+            queue_total: fields[2],
+            copy_mode: fields[3] as u8,
+            copy_range: fields[4],
+            queue_dropped: fields[5],
+            user_dropped: fields[6],
+            id_sequence: fields[7]
+        })
+    }).find(|stats| stats.queue_num == queue_num)
+}
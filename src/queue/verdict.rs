@@ -1,8 +1,126 @@
 //! Verdict and packet handling for NFQueue packets.
+use errno::Errno;
 use libc::*;
+use std::ptr::null;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
 use error::*;
 use ffi::*;
-use ffi::nfq_q_handle as QueueHandle;
+use handle::HandleState;
+use lock::NFQ_LOCK as LOCK;
+use super::stats::QueueCounters;
+use super::CopyMode;
+
+/// A handle to a queue, used to set the verdicts of its packets
+///
+/// `QueueHandle` is `Send` and `Sync`, so verdicts may be set from other threads than the one
+/// dispatching packets; setting verdicts is serialized by the library lock.
+/// A `QueueHandle` may outlive its `Queue`, and the `Handle` it was created on, but once the queue
+/// is destroyed, anything set through the handle fails with `Reason::QueueDestroyed`.
+#[derive(Clone)]
+pub struct QueueHandle {
+    state: Arc<QueueState>
+}
+
+// The state of a `Queue` shared with its `QueueHandle`s, which lives as long as any of them
+pub struct QueueState {
+    // Null once the queue is destroyed; this is only changed under the library lock
+    pub ptr: AtomicPtr<nfq_q_handle>,
+    pub stats: QueueCounters,
+    // The copy mode last set through the queue or its `QueueHandle`s
+    pub copy_mode: Mutex<CopyMode>,
+    // Keeps the library's handle open for as long as the queue may be used
+    _handle: Arc<HandleState>
+}
+
+impl QueueState {
+    pub fn new(handle: Arc<HandleState>) -> QueueState {
+        QueueState {
+            ptr: AtomicPtr::default(),
+            stats: QueueCounters::default(),
+            // A new queue copies nothing
+            copy_mode: Mutex::new(CopyMode::None),
+            _handle: handle
+        }
+    }
+}
+
+impl QueueHandle {
+    pub(crate) fn new(state: Arc<QueueState>) -> QueueHandle {
+        QueueHandle { state: state }
+    }
+
+    // The queue's pointer, unless it has been destroyed
+    // The library lock must be held for as long as the pointer is used.
+    fn ptr(&self) -> Result<*mut nfq_q_handle, Error> {
+        let ptr = self.state.ptr.load(Ordering::Relaxed);
+        if ptr.is_null() {
+            Err(error_with_errno(Reason::QueueDestroyed, "Queue has been destroyed", None, Errno(0)))
+        } else {
+            Ok(ptr)
+        }
+    }
+
+    /// Accept the packet `packet_id`
+    ///
+    /// This is shorthand for `Verdict::set_verdict` with `Verdict::Accept` and no payload.
+    pub fn accept(&self, packet_id: u32) -> Result<(), Error> {
+        self.set(packet_id, Verdict::Accept)
+    }
+
+    /// Accept the packet `packet_id`, setting its nfmark to `mark`
+    ///
+    /// This is shorthand for `Verdict::set_verdict_mark` with `Verdict::Accept`,
+    /// for handlers which classify packets for later `iptables` rules.
+    pub fn accept_with_mark(&self, packet_id: u32, mark: u32) -> Result<(), Error> {
+        Verdict::set_verdict_mark(self, packet_id, Verdict::Accept, mark).map(|_| ())
+    }
+
+    /// Drop the packet `packet_id`
+    pub fn drop(&self, packet_id: u32) -> Result<(), Error> {
+        self.set(packet_id, Verdict::Drop)
+    }
+
+    /// Call the hook again for the packet `packet_id`
+    pub fn repeat(&self, packet_id: u32) -> Result<(), Error> {
+        self.set(packet_id, Verdict::Repeat)
+    }
+
+    /// Set the copy-mode of the queue
+    ///
+    /// This is `Queue::set_mode`, callable wherever verdicts can be set, so that the copy range
+    /// can be changed while the queue is running. It applies only to packets queued afterwards.
+    pub fn set_mode(&self, mode: CopyMode) -> Result<(), Error> {
+        let _lock = LOCK.lock().unwrap();
+        let (copy_mode, range) = mode.as_raw();
+
+        let res = unsafe { nfq_set_mode(self.ptr()?, copy_mode, range) };
+        if res != 0 {
+            Err(error(Reason::SetQueueMode, "Failed to set queue mode", Some(res)))
+        } else {
+            *self.state.copy_mode.lock().unwrap() = mode;
+            Ok(())
+        }
+    }
+
+    fn set(&self, packet_id: u32, verdict: Verdict) -> Result<(), Error> {
+        Verdict::set_verdict_payload(self, packet_id, verdict, &[]).map(|_| ())
+    }
+
+    fn count(&self, counter: fn(&QueueCounters) -> &AtomicU64) {
+        QueueCounters::increment(counter(&self.state.stats));
+    }
+
+    // Count the outcome of setting the verdict of a single packet
+    fn record(&self, verdict: &Verdict, result: c_int) {
+        match (result, verdict) {
+            (-1, _) => self.count(|s| &s.errors),
+            (_, &Verdict::Accept) | (_, &Verdict::Stop) => self.count(|s| &s.accepted),
+            (_, &Verdict::Drop) => self.count(|s| &s.dropped),
+            _ => ()
+        }
+    }
+}
 
 /// Packet verdict used to notify netfilter of a packet's destiny
 pub enum Verdict {
@@ -15,6 +133,11 @@ pub enum Verdict {
     /// This is used when userspace (this program) will finish handling the packet.
     Stolen,
     /// Queue the packet into the given queue_number
+    ///
+    /// This forwards the packet to another NFQUEUE, e.g. for a second stage of processing.
+    /// The verdict is encoded with `NF_QUEUE` (3) in bits 0-15 and the target queue number
+    /// in bits 16-31, so `Queue(2)` is passed to the kernel as `0x0002_0003`.
+    /// If no process is bound to the target queue, the packet is dropped.
     Queue(u16),
     /// Call this hook again for this packet
     ///
@@ -26,12 +149,13 @@ pub enum Verdict {
 
 impl Verdict {
     // Encodes the enum into a u32 suitible for use by nfq_set_verdict
-    fn as_u32(&self) -> u32 {
+    #[doc(hidden)]
+    pub fn as_u32(&self) -> u32 {
         match *self {
             Verdict::Drop => NF_DROP,
             Verdict::Accept => NF_ACCEPT,
             Verdict::Stolen => NF_STOLEN,
-            Verdict::Queue(t) => NF_QUEUE | (t as u32) << 16,
+            Verdict::Queue(t) => NF_QUEUE | (t as u32) << NF_VERDICT_QBITS,
             Verdict::Repeat => NF_REPEAT,
             Verdict::Stop => NF_STOP,
         }
@@ -40,13 +164,119 @@ impl Verdict {
     /// Set the verdict for a packet
     ///
     /// The `packet_id` must be used to identify a packet, fetched from `packet.header.id()`.
-    /// For simpler cases, pass `data_len = 0` and `buffer = std::ptr::null()`.
-    pub fn set_verdict(qh: *mut QueueHandle, packet_id: u32, verdict: Verdict, data_len: u32, buffer: *const c_uchar) -> Result<c_int, Error> {
+    /// For simpler cases, pass `data_len = 0` and `buffer = std::ptr::null()`,
+    /// or use `set_verdict_payload`, which takes the new contents of the packet as a slice.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be null, or point to at least `data_len` readable bytes.
+    pub unsafe fn set_verdict(qh: &QueueHandle, packet_id: u32, verdict: Verdict, data_len: u32, buffer: *const c_uchar) -> Result<c_int, Error> {
+        let _lock = LOCK.lock().unwrap();
 	let c_verdict = verdict.as_u32() as uint32_t;
 
-        match unsafe { nfq_set_verdict(qh, packet_id as uint32_t, c_verdict as uint32_t, data_len as uint32_t, buffer) } {
+        let res = nfq_set_verdict(qh.ptr()?, packet_id as uint32_t, c_verdict as uint32_t, data_len as uint32_t, buffer);
+        qh.record(&verdict, res);
+        match res {
             -1 => Err(error(Reason::SetVerdict, "Failed to set verdict", None)),
-            r @ _ => Ok(r)
+            r => Ok(r)
+        }
+    }
+
+    /// Set the verdict for a packet, replacing its contents with `payload`
+    ///
+    /// This allows a mangled packet to be re-injected.
+    /// The caller is responsible for making sure that the IP and TCP/UDP checksums of `payload` are correct.
+    /// An empty `payload` leaves the packet unmodified.
+    pub fn set_verdict_payload(qh: &QueueHandle, packet_id: u32, verdict: Verdict, payload: &[u8]) -> Result<c_int, Error> {
+        let buffer = if payload.is_empty() { null() } else { payload.as_ptr() };
+        unsafe { Verdict::set_verdict(qh, packet_id, verdict, payload.len() as u32, buffer) }
+    }
+
+    /// Set the verdict for every queued packet up to and including `last_packet_id`
+    ///
+    /// This affects all packets in the queue with an id less than or equal to `last_packet_id`,
+    /// in a single call, which saves a syscall per packet when verdicts are uniform.
+    pub fn set_verdict_batch(qh: &QueueHandle, last_packet_id: u32, verdict: Verdict) -> Result<c_int, Error> {
+        let _lock = LOCK.lock().unwrap();
+        let c_verdict = verdict.as_u32() as uint32_t;
+
+        // The number of packets affected is unknown, so only failures are counted
+        let res = unsafe { nfq_set_verdict_batch(qh.ptr()?, last_packet_id as uint32_t, c_verdict) };
+        if res == -1 {
+            qh.count(|s| &s.errors);
         }
+        match res {
+            -1 => Err(error(Reason::SetVerdict, "Failed to set batch verdict", None)),
+            r => Ok(r)
+        }
+    }
+
+    /// Set the verdict for a packet, along with its nfmark
+    ///
+    /// The `mark` is given in host byte order and is applied to the packet as it is released,
+    /// so that later `iptables` rules may match on it.
+    pub fn set_verdict_mark(qh: &QueueHandle, packet_id: u32, verdict: Verdict, mark: u32) -> Result<c_int, Error> {
+        Verdict::set_verdict2_payload(qh, packet_id, verdict, mark, &[])
+    }
+
+    /// Set the verdict for a packet, along with its nfmark, replacing its contents with `payload`
+    ///
+    /// This combines `set_verdict_mark` and `set_verdict_payload` in a single call.
+    /// The `mark` is given in host byte order, and is converted to network byte order by the library.
+    /// An empty `payload` leaves the packet unmodified, while still applying the mark.
+    /// The caller is responsible for making sure that the IP and TCP/UDP checksums of `payload` are correct.
+    pub fn set_verdict2_payload(qh: &QueueHandle, packet_id: u32, verdict: Verdict, mark: u32, payload: &[u8]) -> Result<c_int, Error> {
+        let _lock = LOCK.lock().unwrap();
+        let c_verdict = verdict.as_u32() as uint32_t;
+        let buffer = if payload.is_empty() { null() } else { payload.as_ptr() };
+
+        let res = unsafe {
+            nfq_set_verdict2(qh.ptr()?, packet_id as uint32_t, c_verdict, mark as uint32_t,
+                             payload.len() as uint32_t, buffer)
+        };
+        qh.record(&verdict, res);
+        match res {
+            -1 => Err(error(Reason::SetVerdict, "Failed to set verdict", None)),
+            r => Ok(r)
+        }
+    }
+}
+
+/// The verdict of a single packet, to be set later
+///
+/// This captures the packet id and `QueueHandle` in the callback, so that the verdict can be
+/// decided elsewhere, e.g. on another thread. It keeps the queue's shared state alive, so may
+/// outlive the `Queue` and its `Handle`, but once the queue is destroyed, setting its verdict
+/// fails with `Reason::QueueDestroyed`.
+/// Dropping a `PendingVerdict` without setting a verdict leaves the packet in the kernel's queue.
+pub struct PendingVerdict {
+    qh: QueueHandle,
+    id: u32
+}
+
+impl PendingVerdict {
+    /// Create a token for the packet `packet_id` of the queue `qh`
+    pub fn new(qh: QueueHandle, packet_id: u32) -> PendingVerdict {
+        PendingVerdict { qh: qh, id: packet_id }
+    }
+
+    /// The id of the packet
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Set the verdict for the packet
+    pub fn set(self, verdict: Verdict) -> Result<(), Error> {
+        self.qh.set(self.id, verdict)
+    }
+
+    /// Accept the packet
+    pub fn accept(self) -> Result<(), Error> {
+        self.set(Verdict::Accept)
+    }
+
+    /// Drop the packet
+    pub fn drop(self) -> Result<(), Error> {
+        self.set(Verdict::Drop)
     }
 }
@@ -0,0 +1,50 @@
+//! Rate limiting for verdicts
+//!
+//! A `RateLimiter` knows nothing of packets, so handlers may keep one per flow, address or queue,
+//! and consult it to choose between `Verdict::Accept` and `Verdict::Drop`.
+use std::time::Instant;
+
+/// A token bucket, allowing `rate_per_sec` events per second in bursts of up to `burst`
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant
+}
+
+impl RateLimiter {
+    /// Create a limiter which starts full, so allows an initial burst
+    pub fn new(rate_per_sec: f64, burst: u32) -> RateLimiter {
+        RateLimiter {
+            rate: rate_per_sec,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last: Instant::now()
+        }
+    }
+
+    /// Whether an event is allowed now, taking a token if so
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    /// Whether an event is allowed at `now`, taking a token if so
+    ///
+    /// Times earlier than the last call add no tokens.
+    pub fn allow_at(&mut self, now: Instant) -> bool {
+        if now > self.last {
+            let elapsed = now.duration_since(self.last);
+            let refill = elapsed.as_secs() as f64 * self.rate
+                + elapsed.subsec_nanos() as f64 * self.rate / 1e9;
+            self.tokens = (self.tokens + refill).min(self.burst);
+            self.last = now;
+        }
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
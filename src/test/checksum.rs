@@ -0,0 +1,61 @@
+use super::super::message::IPHeader;
+use super::super::message::checksum::*;
+
+// 192.168.0.1 -> 192.168.0.199, checksum 0xb861
+const IP_HEADER: [u8; 20] = [0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+                             0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+
+// Port 1234 -> 53, carrying "hello", checksum 0x34e2
+const UDP_DATAGRAM: [u8; 13] = [0x04, 0xd2, 0x00, 0x35, 0x00, 0x0d, 0x34, 0xe2,
+                                0x68, 0x65, 0x6c, 0x6c, 0x6f];
+
+// SYN from port 40000 -> 80, checksum 0x2d9c
+const TCP_SEGMENT: [u8; 20] = [0x9c, 0x40, 0x00, 0x50, 0x12, 0x34, 0x56, 0x78, 0x00, 0x00,
+                               0x00, 0x00, 0x50, 0x02, 0xfa, 0xf0, 0x2d, 0x9c, 0x00, 0x00];
+
+fn ip_header(protocol: u8) -> IPHeader {
+    IPHeader {
+        version_and_header_raw: 0x45,
+        dscp_raw: 0,
+        total_length_raw: 0,
+        id_raw: 0,
+        flags_and_offset_raw: 0,
+        ttl_raw: 64,
+        protocol_raw: protocol,
+        checksum_raw: 0,
+        saddr_raw: u32::to_be(0xc0a80001),
+        daddr_raw: u32::to_be(0xc0a800c7)
+    }
+}
+
+#[test]
+fn ipv4() {
+    assert_eq!(ipv4_checksum(&IP_HEADER), 0xb861);
+}
+
+#[test]
+fn ipv4_ignores_existing_checksum() {
+    let mut header = IP_HEADER;
+    header[10] = 0;
+    header[11] = 0;
+    assert_eq!(ipv4_checksum(&header), 0xb861);
+}
+
+#[test]
+fn tcp() {
+    assert_eq!(tcp_checksum(&ip_header(6), &TCP_SEGMENT), 0x2d9c);
+}
+
+#[test]
+fn udp() {
+    assert_eq!(udp_checksum(&ip_header(17), &UDP_DATAGRAM), 0x34e2);
+}
+
+#[test]
+fn incremental_update() {
+    let mut header = IP_HEADER;
+    // Decrement the TTL, in the word shared with the protocol
+    header[8] = 0x3f;
+    let checksum = update(0xb861, 0x4011, 0x3f11);
+    assert_eq!(checksum, ipv4_checksum(&header));
+}
@@ -0,0 +1,30 @@
+use super::super::handle::ProtocolFamily;
+use super::super::message::{Direction, Hook};
+
+#[test]
+fn inet() {
+    assert_eq!(Direction::from_hook(ProtocolFamily::INET, 0), Direction::Inbound);
+    assert_eq!(Direction::from_hook(ProtocolFamily::INET, 1), Direction::Local);
+    assert_eq!(Direction::from_hook(ProtocolFamily::INET, 2), Direction::Forward);
+    assert_eq!(Direction::from_hook(ProtocolFamily::INET, 3), Direction::Outbound);
+    assert_eq!(Direction::from_hook(ProtocolFamily::INET6, 4), Direction::Outbound);
+    assert_eq!(Direction::from_hook(ProtocolFamily::INET, 5), Direction::Unknown(5));
+}
+
+#[test]
+fn arp() {
+    assert_eq!(Direction::from_hook(ProtocolFamily::ARP, 1), Direction::Outbound);
+    assert_eq!(Direction::from_hook(ProtocolFamily::ARP, 2), Direction::Forward);
+    assert_eq!(Direction::from_hook(ProtocolFamily::NETDEV, 2), Direction::Unknown(2));
+}
+
+#[test]
+fn hooks() {
+    assert_eq!(Hook::from(0), Hook::PreRouting);
+    assert_eq!(Hook::from(2), Hook::Forward);
+    assert_eq!(Hook::from(4), Hook::PostRouting);
+    assert_eq!(Hook::from(7), Hook::Unknown(7));
+    for hook in 0..8u8 {
+        assert_eq!(u8::from(Hook::from(hook)), hook);
+    }
+}
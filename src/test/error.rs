@@ -0,0 +1,41 @@
+use std::error::Error as Base;
+use errno::Errno;
+use libc::{ENOBUFS, EINVAL, ENOPROTOOPT, EPROTONOSUPPORT};
+use super::super::error::{error, error_with_errno, subsystem_error, payload_too_short, Reason};
+
+#[test]
+fn display_includes_reason() {
+    let err = payload_too_short(7, 8);
+    assert_eq!(format!("{}", err), "Payload too short (got: 7, need: 8) (PayloadTooShort { got: 7, need: 8 })");
+}
+
+#[test]
+fn is_std_error() {
+    let err: Box<dyn Base> = Box::new(error(Reason::Recv, "Failed to receive", None));
+    assert!(err.source().is_none());
+}
+
+#[test]
+fn os_error() {
+    let err = error_with_errno(Reason::Recv, "Failed to receive packets", Some(-1), Errno(ENOBUFS));
+    assert_eq!(err.os_error(), Some(ENOBUFS));
+    assert_eq!(payload_too_short(7, 8).os_error(), None);
+}
+
+#[test]
+fn module_not_loaded() {
+    for &errno in &[EPROTONOSUPPORT, ENOPROTOOPT] {
+        let err = subsystem_error(Reason::Bind, "Failed to bind handle", Some(-1), Errno(errno));
+        match *err.reason() {
+            Reason::ModuleNotLoaded => (),
+            ref reason => panic!("Unexpected reason {:?}", reason)
+        }
+        assert_eq!(err.os_error(), Some(errno));
+    }
+
+    let err = subsystem_error(Reason::CreateQueue, "Failed to create queue", None, Errno(EINVAL));
+    match *err.reason() {
+        Reason::CreateQueue => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
@@ -0,0 +1,151 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+use libc::*;
+use super::super::handle::{Handle, HandleBuilder, ProtocolFamily, poll_handles};
+use super::super::error::Reason;
+use super::super::message::Message;
+use super::super::queue::Verdict;
+use super::netlink::{packet_message, send_to_handle};
+
+const LARGE_QUEUE: u16 = 25;
+
+#[test]
+fn read_buffer_size() {
+    let mut handle = Handle::new().ok().unwrap();
+    assert!(handle.read_buffer_size() >= 65536);
+
+    handle.set_read_buffer_size(16384);
+    assert_eq!(handle.read_buffer_size(), 16384);
+}
+
+#[test]
+fn large_packet_untruncated() {
+    let payload: Vec<u8> = (0..60000).map(|i| i as u8).collect();
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let sink = received.clone();
+
+    let mut handle = Handle::new().ok().unwrap();
+    let default_size = handle.read_buffer_size();
+    handle.set_read_buffer_size(256 * 1024);
+    let _queue = handle.queue(LARGE_QUEUE, move |message: &Message| {
+        sink.borrow_mut().extend_from_slice(message.payload_bytes().ok().unwrap());
+        Verdict::Accept
+    }).ok().unwrap();
+
+    let message = packet_message(LARGE_QUEUE, &payload, 128 * 1024);
+    assert!(message.len() > default_size);
+    send_to_handle(&handle, &message);
+
+    assert_eq!(handle.start_n(1).ok(), Some(1));
+    assert_eq!(*received.borrow(), payload);
+}
+
+#[test]
+fn large_packet_truncated_by_default() {
+    let payload = vec![0u8; 60000];
+    let handled = Rc::new(Cell::new(false));
+    let failed = Rc::new(Cell::new(false));
+    let (on_handle, on_error) = (handled.clone(), failed.clone());
+
+    let mut handle = Handle::new().ok().unwrap();
+    handle.on_packet_error(move |_| on_error.set(true));
+    let _queue = handle.queue(LARGE_QUEUE + 1, move |_: &Message| {
+        on_handle.set(true);
+        Verdict::Accept
+    }).ok().unwrap();
+
+    let message = packet_message(LARGE_QUEUE + 1, &payload, 128 * 1024);
+    assert!(message.len() > handle.read_buffer_size());
+    send_to_handle(&handle, &message);
+
+    assert_eq!(handle.start_n(1).ok(), Some(1));
+    assert!(!handled.get());
+    assert!(failed.get());
+}
+
+#[test]
+fn protocol_families_distinct() {
+    let families = [ProtocolFamily::INET as u16,
+                    ProtocolFamily::INET6 as u16,
+                    ProtocolFamily::ARP as u16,
+                    ProtocolFamily::BRIDGE as u16,
+                    ProtocolFamily::NETDEV as u16];
+    for (i, a) in families.iter().enumerate() {
+        for b in &families[i + 1..] {
+            assert!(a != b);
+        }
+    }
+}
+
+#[test]
+fn into_raw_fd() {
+    let handle = Handle::new().ok().unwrap();
+    let fd = handle.fd();
+    assert_eq!(handle.into_raw_fd(), fd);
+}
+
+#[test]
+fn builder() {
+    let handle = HandleBuilder::new()
+        .protocol_family(ProtocolFamily::INET)
+        .read_buffer(4096)
+        .build()
+        .ok().unwrap();
+    assert_eq!(handle.read_buffer_size(), 4096);
+}
+
+#[test]
+fn no_enobufs() {
+    let mut handle = Handle::new().ok().unwrap();
+    assert!(handle.set_no_enobufs(true).is_ok());
+    assert!(handle.set_no_enobufs(false).is_ok());
+}
+
+#[test]
+fn start_none() {
+    let mut handle = Handle::new().ok().unwrap();
+    assert_eq!(handle.start_n(0).ok(), Some(0));
+}
+
+#[test]
+fn from_raw_fd() {
+    let owner = Handle::new().ok().unwrap();
+    let handle = Handle::from_raw_fd(owner.fd(), false).ok().unwrap();
+    assert!(handle.fd() != owner.fd());
+
+    // Dropping the handle leaves the original socket open
+    drop(handle);
+    assert!(owner.into_raw_fd() >= 0);
+}
+
+#[test]
+fn from_raw_fd_owned() {
+    let fd = Handle::new().ok().unwrap().into_raw_fd();
+    let handle = Handle::from_raw_fd(fd, true).ok().unwrap();
+    assert!(handle.fd() != fd);
+    assert!(unsafe { fcntl(fd, F_GETFD) } >= 0);
+
+    // The handle closes `fd` only once dropped
+    drop(handle);
+    assert!(unsafe { fcntl(fd, F_GETFD) } < 0);
+}
+
+#[test]
+fn from_raw_fd_not_netfilter() {
+    let fd = unsafe { socket(AF_INET, SOCK_DGRAM, 0) };
+    let err = Handle::from_raw_fd(fd, false).err().unwrap();
+    unsafe { close(fd) };
+    match *err.reason() {
+        Reason::OpenHandle => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+    assert_eq!(err.os_error(), None);
+}
+
+#[test]
+fn poll_idle_handles() {
+    let mut v4 = Handle::new().ok().unwrap();
+    let mut v6 = Handle::new().ok().unwrap();
+    assert!(poll_handles(&mut [&mut v4, &mut v6], Duration::from_millis(10)).is_ok());
+}
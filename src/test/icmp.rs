@@ -0,0 +1,52 @@
+use super::super::error::Reason;
+use super::super::message::build_icmp_unreachable;
+use super::super::message::checksum::{icmp_checksum, ipv4_checksum};
+
+// 10.0.0.1:1234 -> 10.0.0.2:53 over UDP, carrying "hello"
+const UDP_PACKET: [u8; 33] = [0x45, 0x00, 0x00, 0x21, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+                              0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02,
+                              0x04, 0xd2, 0x00, 0x35, 0x00, 0x0d, 0x00, 0x00,
+                              0x68, 0x65, 0x6c, 0x6c, 0x6f];
+
+fn be16(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) << 8 | bytes[1] as u16
+}
+
+#[test]
+fn port_unreachable() {
+    let packet = build_icmp_unreachable(&UDP_PACKET, 3).ok().unwrap();
+    assert_eq!(packet.len(), 20 + 8 + 28);
+    assert_eq!(be16(&packet[2..4]), 56);
+    assert_eq!(packet[9], 1);
+    assert_eq!(&packet[12..16], &[10, 0, 0, 2]);
+    assert_eq!(&packet[16..20], &[10, 0, 0, 1]);
+    assert_eq!(be16(&packet[10..12]), ipv4_checksum(&packet[..20]));
+
+    let icmp = &packet[20..];
+    assert_eq!(icmp[0], 3);
+    assert_eq!(icmp[1], 3);
+    assert_eq!(be16(&icmp[2..4]), icmp_checksum(icmp));
+    assert_eq!(&icmp[8..], &UDP_PACKET[..28]);
+}
+
+#[test]
+fn short_original() {
+    assert_eq!(build_icmp_unreachable(&UDP_PACKET[..12], 3).err().unwrap().payload_sizes(), Some((12, 20)));
+    // With fewer than 8 bytes of payload, all of it is quoted
+    assert_eq!(build_icmp_unreachable(&UDP_PACKET[..24], 3).ok().unwrap().len(), 20 + 8 + 24);
+}
+
+#[test]
+fn invalid_original() {
+    let mut ipv6 = UDP_PACKET;
+    ipv6[0] = 0x65;
+    match *build_icmp_unreachable(&ipv6, 3).err().unwrap().reason() {
+        Reason::InvalidPacket => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+
+    // An IHL of 6 words needs 24 bytes of header
+    let mut options = UDP_PACKET;
+    options[0] = 0x46;
+    assert_eq!(build_icmp_unreachable(&options[..22], 3).err().unwrap().payload_sizes(), Some((22, 24)));
+}
@@ -0,0 +1,307 @@
+use std::cell::RefCell;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::ptr;
+use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
+use super::super::error::Reason;
+use super::super::ffi::{NFQA_MARK, NFQA_TIMESTAMP, NFQA_IFINDEX_INDEV, NFQA_HWADDR, NFQA_UID, NFQA_GID};
+use super::super::handle::Handle;
+use super::super::message::{view, ip_packet_length, l4_payload, IPHeader, UdpHeader, Protocol, Message,
+                            ExtractOpts, ExtractedPacket};
+use super::super::message::checksum::ipv4_checksum;
+use super::super::queue::Verdict;
+use super::netlink::{packet_message_with, send_to_handle};
+
+const EXTRACT_QUEUE: u16 = 26;
+
+#[test]
+fn view_short_payload() {
+    let bytes = [0u8; 7];
+    assert!(unsafe { view::<UdpHeader>(&bytes) }.is_err());
+}
+
+#[test]
+fn view_short_payload_sizes() {
+    let bytes = [0u8; 7];
+    let err = unsafe { view::<UdpHeader>(&bytes) }.err().unwrap();
+    assert_eq!(err.payload_sizes(), Some((7, 8)));
+}
+
+#[test]
+fn view_exact_payload() {
+    let bytes = [0u8; 8];
+    assert!(unsafe { view::<UdpHeader>(&bytes) }.is_ok());
+}
+
+#[test]
+fn ip_header_length() {
+    let mut header = IPHeader {
+        version_and_header_raw: 0x45,
+        dscp_raw: 0,
+        total_length_raw: 0,
+        id_raw: 0,
+        flags_and_offset_raw: 0,
+        ttl_raw: 64,
+        protocol_raw: 6,
+        checksum_raw: 0,
+        saddr_raw: 0,
+        daddr_raw: 0
+    };
+    assert_eq!(header.header_length(), 20);
+
+    // With 4 bytes of options
+    header.version_and_header_raw = 0x46;
+    assert_eq!(header.header_length(), 24);
+}
+
+#[test]
+fn ip_packet_lengths() {
+    assert_eq!(ip_packet_length(&[0x45, 0, 0x05, 0xdc]), Some(1500));
+    assert_eq!(ip_packet_length(&[0x60, 0, 0, 0, 0x00, 0x14]), Some(60));
+    assert_eq!(ip_packet_length(&[0x45, 0]), None);
+    assert_eq!(ip_packet_length(&[]), None);
+}
+
+#[test]
+fn dscp_and_ecn() {
+    let mut header = IPHeader {
+        version_and_header_raw: 0x45,
+        dscp_raw: 0xb9, // EF with ECT(1)
+        total_length_raw: 0,
+        id_raw: 0,
+        flags_and_offset_raw: 0,
+        ttl_raw: 64,
+        protocol_raw: 6,
+        checksum_raw: 0,
+        saddr_raw: 0,
+        daddr_raw: 0
+    };
+    assert_eq!(header.dscp(), 46);
+    assert_eq!(header.ecn(), 1);
+
+    header.set_dscp(10);
+    assert_eq!(header.dscp(), 10);
+    assert_eq!(header.ecn(), 1);
+}
+
+// The second fragment of a 4000 byte ICMP echo request
+const FRAGMENT: [u8; 20] = [0x45, 0x00, 0x05, 0xdc, 0x1c, 0x46, 0x20, 0xb9, 0x40, 0x01,
+                            0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02];
+
+// Build a header from wire bytes field by field, keeping the raw fields in network order
+fn ip_header_from(b: &[u8; 20]) -> IPHeader {
+    IPHeader {
+        version_and_header_raw: b[0],
+        dscp_raw: b[1],
+        total_length_raw: u16::from_ne_bytes([b[2], b[3]]),
+        id_raw: u16::from_ne_bytes([b[4], b[5]]),
+        flags_and_offset_raw: u16::from_ne_bytes([b[6], b[7]]),
+        ttl_raw: b[8],
+        protocol_raw: b[9],
+        checksum_raw: u16::from_ne_bytes([b[10], b[11]]),
+        saddr_raw: u32::from_ne_bytes([b[12], b[13], b[14], b[15]]),
+        daddr_raw: u32::from_ne_bytes([b[16], b[17], b[18], b[19]])
+    }
+}
+
+#[test]
+fn fragment() {
+    let header = ip_header_from(&FRAGMENT);
+    assert!(!header.dont_fragment());
+    assert!(header.more_fragments());
+    assert_eq!(header.fragment_offset(), 1480);
+    assert!(header.is_fragment());
+}
+
+#[test]
+fn dont_fragment() {
+    let mut bytes = FRAGMENT;
+    bytes[6] = 0x40;
+    bytes[7] = 0x00;
+    let header = ip_header_from(&bytes);
+    assert!(header.dont_fragment());
+    assert!(!header.more_fragments());
+    assert_eq!(header.fragment_offset(), 0);
+    assert!(!header.is_fragment());
+}
+
+#[test]
+fn total_length() {
+    let header = ip_header_from(&FRAGMENT);
+    assert_eq!(header.total_length(), 1500);
+    assert_eq!(header.total_length() as usize - header.header_length(), 1480);
+}
+
+#[test]
+fn decremented_ttl() {
+    let mut bytes = FRAGMENT;
+    let checksum = ipv4_checksum(&bytes).to_be_bytes();
+    bytes[10] = checksum[0];
+    bytes[11] = checksum[1];
+    let header = ip_header_from(&bytes);
+    assert_eq!(header.ttl(), 64);
+
+    let decremented = header.with_decremented_ttl();
+    assert_eq!(decremented[8], 63);
+    assert_eq!(&decremented[..8], &bytes[..8]);
+    assert_eq!(&decremented[12..], &bytes[12..]);
+    let checksum = ipv4_checksum(&decremented).to_be_bytes();
+    assert_eq!(&decremented[10..12], &checksum[..]);
+}
+
+// 10.0.0.1:1234 -> 10.0.0.2:53 over UDP, carrying "hello" and 2 bytes of Ethernet padding
+const UDP_PACKET: [u8; 35] = [0x45, 0x00, 0x00, 0x21, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+                              0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02,
+                              0x04, 0xd2, 0x00, 0x35, 0x00, 0x0d, 0x00, 0x00,
+                              0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x00, 0x00];
+
+#[test]
+fn l4_payload_udp() {
+    assert_eq!(l4_payload(&UDP_PACKET).ok().unwrap(), b"hello");
+}
+
+#[test]
+fn l4_payload_tcp_options() {
+    // A SYN with a 24-byte header, carrying a 4-byte MSS option and no data
+    let mut bytes = [0u8; 44];
+    bytes[..20].copy_from_slice(&UDP_PACKET[..20]);
+    bytes[3] = 44;
+    bytes[9] = 6;
+    bytes[32] = 0x60;
+    bytes[33] = 0x02;
+    assert_eq!(l4_payload(&bytes).ok().unwrap().len(), 0);
+    assert_eq!(l4_payload(&bytes[..40]).err().unwrap().payload_sizes(), Some((40, 44)));
+}
+
+#[test]
+fn l4_payload_not_ipv4() {
+    let mut ipv6 = UDP_PACKET;
+    ipv6[0] = 0x65;
+    match *l4_payload(&ipv6).err().unwrap().reason() {
+        Reason::InvalidPacket => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
+
+#[test]
+fn l4_payload_invalid_header_lengths() {
+    // An IHL of 4 words is shorter than the fixed IPv4 header
+    let mut ihl = UDP_PACKET;
+    ihl[0] = 0x44;
+    match *l4_payload(&ihl).err().unwrap().reason() {
+        Reason::InvalidPacket => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+
+    // A data offset of 4 words is shorter than the fixed TCP header
+    let mut bytes = [0u8; 40];
+    bytes[..20].copy_from_slice(&UDP_PACKET[..20]);
+    bytes[3] = 40;
+    bytes[9] = 6;
+    bytes[32] = 0x40;
+    match *l4_payload(&bytes).err().unwrap().reason() {
+        Reason::InvalidPacket => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
+
+#[test]
+fn l4_payload_short() {
+    assert_eq!(l4_payload(&UDP_PACKET[..24]).err().unwrap().payload_sizes(), Some((4, 8)));
+}
+
+#[test]
+fn addresses() {
+    let mut bytes = FRAGMENT;
+    bytes[12..16].copy_from_slice(&[192, 168, 1, 1]);
+    bytes[16..20].copy_from_slice(&[10, 0, 0, 254]);
+    let header = ip_header_from(&bytes);
+    assert_eq!(header.saddr(), Ipv4Addr::new(192, 168, 1, 1));
+    assert_eq!(header.daddr(), Ipv4Addr::new(10, 0, 0, 254));
+}
+
+#[test]
+fn to_host() {
+    let header = ip_header_from(&FRAGMENT);
+    let host = header.to_host();
+    assert_eq!(host.version, 4);
+    assert_eq!(host.header_length, 20);
+    assert_eq!(host.total_length, 1500);
+    assert_eq!(host.id, 0x1c46);
+    assert!(host.more_fragments);
+    assert_eq!(host.fragment_offset, 1480);
+    assert_eq!(host.ttl, 64);
+    assert_eq!(host.protocol, Protocol::Icmp);
+    assert_eq!(host.saddr, Ipv4Addr::new(10, 0, 0, 1));
+    assert_eq!(host.daddr, Ipv4Addr::new(10, 0, 0, 2));
+
+    // The raw fields are still in network order
+    assert_eq!({ header.id_raw }, u16::from_ne_bytes([0x1c, 0x46]));
+}
+
+#[test]
+fn ip_header_layout() {
+    assert_eq!(mem::size_of::<IPHeader>(), 20);
+    assert_eq!(mem::align_of::<IPHeader>(), 1);
+
+    // Viewing the wire bytes directly must agree with reading them field by field
+    let viewed = unsafe { view::<IPHeader>(&FRAGMENT) }.ok().unwrap();
+    assert_eq!(viewed.to_host(), ip_header_from(&FRAGMENT).to_host());
+
+    let base = viewed as *const IPHeader as usize;
+    assert_eq!(ptr::addr_of!(viewed.ttl_raw) as usize - base, 8);
+    assert_eq!(ptr::addr_of!(viewed.checksum_raw) as usize - base, 10);
+    assert_eq!(ptr::addr_of!(viewed.saddr_raw) as usize - base, 12);
+    assert_eq!(ptr::addr_of!(viewed.daddr_raw) as usize - base, 16);
+}
+
+#[test]
+fn extract_matches_accessors() {
+    let mut timestamp = 1_600_000_000u64.to_be_bytes().to_vec();
+    timestamp.extend_from_slice(&250_000u64.to_be_bytes());
+    let hw_addr = [0x00, 0x06, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+    let message = packet_message_with(EXTRACT_QUEUE, &[
+        (NFQA_MARK, &7u32.to_be_bytes()),
+        (NFQA_TIMESTAMP, &timestamp),
+        (NFQA_IFINDEX_INDEV, &2u32.to_be_bytes()),
+        (NFQA_HWADDR, &hw_addr),
+        (NFQA_UID, &1000u32.to_be_bytes()),
+        (NFQA_GID, &100u32.to_be_bytes())
+    ], &[0x45; 20]);
+
+    // Handler panics are caught, so the packets are compared once dispatched
+    let packets = Rc::new(RefCell::new(Vec::new()));
+    let sink = packets.clone();
+    let mut handle = Handle::new().ok().unwrap();
+    let _queue = handle.queue(EXTRACT_QUEUE, move |message: &Message| {
+        let accessors = ExtractedPacket {
+            id: message.header.id(),
+            mark: Some(message.mark()),
+            timestamp: message.timestamp().ok(),
+            in_dev: message.in_dev(),
+            out_dev: message.out_dev(),
+            phys_in_dev: message.phys_in_dev(),
+            phys_out_dev: message.phys_out_dev(),
+            hw_addr: message.hw_addr(),
+            uid: message.uid(),
+            gid: message.gid(),
+            payload: message.payload_bytes().ok().map(|p| p.to_vec())
+        };
+        sink.borrow_mut().push((message.extract(ExtractOpts::all()), accessors));
+        Verdict::Accept
+    }).ok().unwrap();
+    send_to_handle(&handle, &message);
+    assert_eq!(handle.start_n(1).ok(), Some(1));
+
+    let (ref extracted, ref accessors) = packets.borrow()[0];
+    assert_eq!(extracted, accessors);
+    assert_eq!(extracted.mark, Some(7));
+    assert_eq!(extracted.timestamp, Some(UNIX_EPOCH + Duration::new(1_600_000_000, 250_000_000)));
+    assert_eq!(extracted.in_dev, Some(2));
+    assert_eq!(extracted.out_dev, None);
+    assert_eq!(extracted.hw_addr, Some([0x02, 0, 0, 0, 0, 0x01]));
+    assert_eq!(extracted.uid, Some(1000));
+    assert_eq!(extracted.gid, Some(100));
+    assert_eq!(extracted.payload, Some(vec![0x45; 20]));
+}
@@ -0,0 +1,89 @@
+use super::super::error::Reason;
+use super::super::message::{Message, Protocol, L4, ExtractOpts, FlowTracker, XmlFlags};
+use super::super::queue::Verdict;
+
+// 10.0.0.1:12345 -> 10.0.0.2:80, a TCP SYN
+const TCP_SYN: [u8; 40] = [0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06,
+                           0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02,
+                           0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+                           0x00, 0x00, 0x50, 0x02, 0x72, 0x10, 0x00, 0x00, 0x00, 0x00];
+
+// Drop connections to port 80
+fn decide(message: &Message) -> Verdict {
+    match message.l4() {
+        Ok(L4::Tcp(tcp)) if tcp.dport() == 80 => Verdict::Drop,
+        _ => Verdict::Accept
+    }
+}
+
+#[test]
+fn from_bytes() {
+    let message = Message::from_bytes(7, &TCP_SYN);
+    assert_eq!(message.header.id(), 7);
+    assert_eq!(message.payload_bytes().ok().unwrap().len(), 40);
+    assert_eq!(message.parse_packet().ok().unwrap().protocol, Protocol::Tcp);
+    assert_eq!(decide(&message).as_u32(), Verdict::Drop.as_u32());
+}
+
+#[test]
+fn from_bytes_metadata() {
+    let message = Message::from_bytes(7, &TCP_SYN);
+    assert_eq!(message.mark(), 0);
+    assert!(message.timestamp().is_err());
+    assert_eq!(message.in_dev(), None);
+    assert_eq!(message.hw_addr(), None);
+    assert_eq!(message.uid(), None);
+    assert_eq!(message.secctx(), None);
+    assert!(!message.is_gso());
+}
+
+#[test]
+fn from_bytes_short() {
+    let message = Message::from_bytes(7, &TCP_SYN[..30]);
+    assert_eq!(decide(&message).as_u32(), Verdict::Accept.as_u32());
+}
+
+#[test]
+fn l4_not_ipv4() {
+    let mut ipv6 = TCP_SYN;
+    ipv6[0] = 0x60;
+    let message = Message::from_bytes(7, &ipv6);
+    match *message.l4().err().unwrap().reason() {
+        Reason::InvalidPacket => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
+
+#[test]
+fn extract_payload() {
+    let message = Message::from_bytes(7, &TCP_SYN);
+    let packet = message.extract(ExtractOpts::PAYLOAD);
+    assert_eq!(packet.id, 7);
+    assert_eq!(packet.payload, Some(TCP_SYN.to_vec()));
+    assert_eq!(packet.mark, None);
+}
+
+#[test]
+fn flow_tracker() {
+    let mut reply = TCP_SYN;
+    reply[12..16].copy_from_slice(&TCP_SYN[16..20]);
+    reply[16..20].copy_from_slice(&TCP_SYN[12..16]);
+    reply[20..22].copy_from_slice(&TCP_SYN[22..24]);
+    reply[22..24].copy_from_slice(&TCP_SYN[20..22]);
+
+    let mut tracker = FlowTracker::new();
+    let flow = tracker.observe(&Message::from_bytes(1, &TCP_SYN)).unwrap();
+    assert_eq!(tracker.observe(&Message::from_bytes(2, &reply)), Some(flow));
+    assert_eq!(tracker.packets(&flow), 2);
+    assert_eq!(tracker.len(), 1);
+
+    assert_eq!(tracker.observe(&Message::from_bytes(3, &TCP_SYN[..10])), None);
+    assert_eq!(tracker.remove(&flow), Some(2));
+    assert!(tracker.is_empty());
+}
+
+#[test]
+fn to_xml_without_packet_data() {
+    let message = Message::from_bytes(7, &TCP_SYN);
+    assert!(message.to_xml(XmlFlags::ALL).is_err());
+}
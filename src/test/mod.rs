@@ -1,3 +1,17 @@
-mod simple;
-mod header;
-mod data;
+mod verdict;
+mod udp;
+mod checksum;
+mod handle;
+mod queue;
+mod message;
+mod error;
+mod stats;
+mod vlan;
+mod parsed;
+mod protocol;
+mod mock;
+mod direction;
+mod tcp;
+mod rate;
+mod icmp;
+mod netlink;
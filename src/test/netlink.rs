@@ -0,0 +1,82 @@
+// Netlink messages crafted as the kernel would send them, to drive the dispatch loops
+use std::mem;
+use libc::*;
+use super::super::handle::Handle;
+use super::super::ffi::{NETLINK_NETFILTER, NFQA_PAYLOAD};
+
+const AF_NETLINK: c_int = 16;
+// (NFNL_SUBSYS_QUEUE << 8) | NFQNL_MSG_PACKET
+const NFQNL_PACKET: u16 = 3 << 8;
+const NFQA_PACKET_HDR: u16 = 1;
+// Above NFQA_MAX, so the library skips it
+const NFQA_UNKNOWN: u16 = 0x7fff;
+
+#[repr(C)]
+struct SockaddrNl {
+    nl_family: sa_family_t,
+    nl_pad: u16,
+    nl_pid: u32,
+    nl_groups: u32
+}
+
+fn push_attribute(message: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    message.extend_from_slice(&((data.len() + 4) as u16).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(data);
+    while !message.len().is_multiple_of(4) {
+        message.push(0);
+    }
+}
+
+// An IPv4 packet message for `queue`, padded to at least `len` bytes with unknown attributes
+pub fn packet_message(queue: u16, payload: &[u8], len: usize) -> Vec<u8> {
+    build_packet(queue, &[], Some(payload), len)
+}
+
+// An IPv4 packet message for `queue`, carrying the metadata `attributes` before its payload
+pub fn packet_message_with(queue: u16, attributes: &[(u16, &[u8])], payload: &[u8]) -> Vec<u8> {
+    build_packet(queue, attributes, Some(payload), 0)
+}
+
+// An IPv4 packet message for `queue` without a payload, as `CopyMode::Metadata` queues it
+pub fn metadata_message(queue: u16) -> Vec<u8> {
+    build_packet(queue, &[], None, 0)
+}
+
+fn build_packet(queue: u16, attributes: &[(u16, &[u8])], payload: Option<&[u8]>, len: usize) -> Vec<u8> {
+    let mut message = vec![0u8; 16];
+    message.extend_from_slice(&[AF_INET as u8, 0]);
+    message.extend_from_slice(&queue.to_be_bytes());
+    push_attribute(&mut message, NFQA_PACKET_HDR, &[0, 0, 0, 1, 0x08, 0x00, 0]);
+    for &(kind, data) in attributes {
+        push_attribute(&mut message, kind, data);
+    }
+    if let Some(payload) = payload {
+        push_attribute(&mut message, NFQA_PAYLOAD, payload);
+    }
+    while message.len() < len {
+        push_attribute(&mut message, NFQA_UNKNOWN, &[0u8; 16384]);
+    }
+
+    let total = message.len() as u32;
+    message[..4].copy_from_slice(&total.to_ne_bytes());
+    message[4..6].copy_from_slice(&NFQNL_PACKET.to_ne_bytes());
+    message
+}
+
+// Send `message` to the handle's socket, as the kernel would
+pub fn send_to_handle(handle: &Handle, message: &[u8]) {
+    let mut addr: SockaddrNl = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<SockaddrNl>() as socklen_t;
+    let res = unsafe { getsockname(handle.fd(), &mut addr as *mut SockaddrNl as *mut sockaddr, &mut len) };
+    assert_eq!(res, 0);
+
+    let fd = unsafe { socket(AF_NETLINK, SOCK_RAW, NETLINK_NETFILTER) };
+    assert!(fd >= 0);
+    let sent = unsafe {
+        sendto(fd, message.as_ptr() as *const c_void, message.len() as size_t, 0,
+               &addr as *const SockaddrNl as *const sockaddr, len)
+    };
+    unsafe { close(fd) };
+    assert_eq!(sent, message.len() as ssize_t);
+}
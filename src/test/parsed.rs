@@ -0,0 +1,74 @@
+use std::net::IpAddr;
+use super::super::error::Reason;
+use super::super::message::{ParsedPacket, Protocol, Transport};
+
+const TCP_SYN: [u8; 40] = [0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06,
+                           0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02,
+                           0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+                           0x00, 0x00, 0x50, 0x02, 0x72, 0x10, 0x00, 0x00, 0x00, 0x00];
+
+#[test]
+fn ipv4_tcp() {
+    let packet = ParsedPacket::parse(&TCP_SYN).ok().unwrap();
+    assert_eq!(packet.saddr, "10.0.0.1".parse::<IpAddr>().unwrap());
+    assert_eq!(packet.daddr, "10.0.0.2".parse::<IpAddr>().unwrap());
+    assert_eq!(packet.protocol, Protocol::Tcp);
+    assert_eq!(packet.length, 40);
+    assert_eq!(packet.transport, Transport::Tcp { sport: 12345, dport: 80, flags: 0x02 });
+    assert_eq!(packet.ports(), Some((12345, 80)));
+}
+
+#[test]
+fn ipv4_fragment() {
+    let mut bytes = TCP_SYN;
+    bytes[6] = 0x00;
+    bytes[7] = 0xb9;
+    let packet = ParsedPacket::parse(&bytes).ok().unwrap();
+    assert_eq!(packet.transport, Transport::Other);
+    assert_eq!(packet.ports(), None);
+}
+
+#[test]
+fn ipv4_truncated_l4() {
+    let packet = ParsedPacket::parse(&TCP_SYN[..24]).ok().unwrap();
+    assert_eq!(packet.protocol, Protocol::Tcp);
+    assert_eq!(packet.transport, Transport::Other);
+}
+
+#[test]
+fn ipv6_udp() {
+    let mut bytes = vec![0x60, 0, 0, 0, 0x00, 0x08, 17, 64];
+    bytes.extend_from_slice(&[0; 15]);
+    bytes.push(1);
+    bytes.extend_from_slice(&[0; 15]);
+    bytes.push(2);
+    bytes.extend_from_slice(&[0x00, 0x35, 0xc0, 0x00, 0x00, 0x08, 0x00, 0x00]);
+
+    let packet = ParsedPacket::parse(&bytes).ok().unwrap();
+    assert_eq!(packet.saddr, "::1".parse::<IpAddr>().unwrap());
+    assert_eq!(packet.daddr, "::2".parse::<IpAddr>().unwrap());
+    assert_eq!(packet.length, 48);
+    assert_eq!(packet.ports(), Some((53, 49152)));
+}
+
+#[test]
+fn too_short() {
+    assert_eq!(ParsedPacket::parse(&TCP_SYN[..10]).err().unwrap().payload_sizes(), Some((10, 20)));
+}
+
+#[test]
+fn invalid_headers() {
+    let mut version = TCP_SYN;
+    version[0] = 0x55;
+    match *ParsedPacket::parse(&version).err().unwrap().reason() {
+        Reason::InvalidPacket => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+
+    let mut ihl = TCP_SYN;
+    ihl[0] = 0x44;
+    match *ParsedPacket::parse(&ihl).err().unwrap().reason() {
+        Reason::InvalidPacket => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
@@ -0,0 +1,26 @@
+use std::convert::TryFrom;
+use super::super::message::Protocol;
+
+#[test]
+fn known() {
+    assert_eq!(Protocol::from(1), Protocol::Icmp);
+    assert_eq!(Protocol::from(6), Protocol::Tcp);
+    assert_eq!(Protocol::from(17), Protocol::Udp);
+    assert!(Protocol::Tcp.is_known());
+}
+
+#[test]
+fn unassigned() {
+    assert_eq!(Protocol::from(143), Protocol::Unknown(143));
+    assert!(!Protocol::from(143).is_known());
+}
+
+#[test]
+// `TryFrom` comes from the blanket impl for `From`, which is what is checked here
+#[allow(clippy::unnecessary_fallible_conversions)]
+fn round_trip() {
+    for p in 0..=255u8 {
+        assert_eq!(u8::from(Protocol::from(p)), p);
+        assert_eq!(Protocol::try_from(p).map(u8::from), Ok(p));
+    }
+}
@@ -0,0 +1,283 @@
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::null;
+use std::rc::Rc;
+use std::thread;
+use super::super::error::{Error, Reason};
+use super::super::handle::{Handle, ProtocolFamily};
+use super::super::message::Message;
+use super::super::queue::{CopyMode, Verdict, QueueHandle, PendingVerdict, ParseErrorPolicy, Raw, RawHandler,
+                          PacketHandler, HandlerResult, FailOpen, FailClosed, nfq_data, nfgenmsg};
+use super::netlink::{packet_message, metadata_message, send_to_handle};
+
+const STALE_QUEUE: u16 = 27;
+const BUSY_QUEUE: u16 = 28;
+const EVENT_QUEUE: u16 = 29;
+
+fn assert_send<T: Send>() {}
+
+#[test]
+fn queue_handle_is_send() {
+    assert_send::<QueueHandle>();
+}
+
+#[test]
+fn pending_verdict_from_thread() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let pending = PendingVerdict::new(queue.handle(), 1);
+
+    let result = thread::spawn(move || pending.drop().is_ok()).join().unwrap();
+    assert!(result);
+    assert_eq!(queue.stats().dropped, 1);
+}
+
+#[test]
+fn pending_verdict_outlives_queue() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let pending = PendingVerdict::new(queue.handle(), 1);
+    assert!(queue.close().is_ok());
+
+    match *pending.accept().err().unwrap().reason() {
+        Reason::QueueDestroyed => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
+
+#[test]
+fn verdict_from_thread() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let qh = queue.handle();
+
+    let result = thread::spawn(move || {
+        unsafe { Verdict::set_verdict(&qh, 1, Verdict::Accept, 0, null()) }.is_ok()
+    }).join().unwrap();
+    assert!(result);
+}
+
+#[test]
+fn stats_count_verdicts() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let qh = queue.handle();
+
+    unsafe {
+        Verdict::set_verdict(&qh, 1, Verdict::Accept, 0, null()).ok().unwrap();
+        Verdict::set_verdict(&qh, 2, Verdict::Drop, 0, null()).ok().unwrap();
+        Verdict::set_verdict(&qh, 3, Verdict::Repeat, 0, null()).ok().unwrap();
+    }
+
+    let stats = queue.stats();
+    assert_eq!(stats.accepted, 1);
+    assert_eq!(stats.dropped, 1);
+    assert_eq!(stats.received, 0);
+    assert_eq!(stats.errors, 0);
+}
+
+#[test]
+fn close() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    assert!(queue.close().is_ok());
+}
+
+#[test]
+fn handle_outlives_queue() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let qh = queue.handle();
+    drop(queue);
+
+    match *qh.accept(1).err().unwrap().reason() {
+        Reason::QueueDestroyed => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
+
+#[test]
+fn queue_outlives_handle() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let qh = queue.handle();
+    let pending = PendingVerdict::new(queue.handle(), 2);
+    drop(handle);
+
+    // The queue keeps the library's handle open, so verdicts can still be set
+    assert!(qh.accept(1).is_ok());
+    assert!(pending.drop().is_ok());
+    assert_eq!(queue.stats().accepted, 1);
+    assert_eq!(queue.stats().dropped, 1);
+    assert!(queue.close().is_ok());
+
+    match *qh.accept(3).err().unwrap().reason() {
+        Reason::QueueDestroyed => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
+
+struct Ignore;
+
+impl RawHandler for Ignore {
+    fn handle(&mut self, _: QueueHandle, _: *mut nfq_data, _: *mut nfgenmsg) -> i32 { 0 }
+}
+
+#[test]
+fn raw_queue() {
+    let mut handle = Handle::new().ok().unwrap();
+    assert!(handle.queue(0, Raw(Ignore)).is_ok());
+}
+
+#[test]
+fn accept_all_pending_without_packets() {
+    let mut handle = Handle::new().ok().unwrap();
+    let mut queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    assert!(queue.accept_all_pending().is_ok());
+}
+
+#[test]
+fn queue_busy() {
+    let mut owner = Handle::new().ok().unwrap();
+    owner.bind(ProtocolFamily::INET).ok().unwrap();
+    let _queue = owner.queue(BUSY_QUEUE, |_: &Message| Verdict::Accept).ok().unwrap();
+
+    let mut other = Handle::new().ok().unwrap();
+    other.bind(ProtocolFamily::INET).ok().unwrap();
+    match *other.queue(BUSY_QUEUE, |_: &Message| Verdict::Accept).err().unwrap().reason() {
+        Reason::QueueBusy => (),
+        ref reason => panic!("Unexpected reason {:?}", reason)
+    }
+}
+
+#[test]
+fn accept_all_pending_after_verdicts() {
+    let errors = Rc::new(Cell::new(0));
+    let counter = errors.clone();
+    let mut handle = Handle::new().ok().unwrap();
+    handle.on_packet_error(move |_| counter.set(counter.get() + 1));
+    let mut queue = handle.queue(STALE_QUEUE, |_: &Message| Verdict::Accept).ok().unwrap();
+
+    send_to_handle(&handle, &packet_message(STALE_QUEUE, &[0u8; 20], 0));
+    assert_eq!(handle.start_n(1).ok(), Some(1));
+    // The kernel never queued the crafted packet, so it also rejects the handler's verdict
+    assert_eq!(handle.process_pending().ok(), Some(1));
+    errors.set(0);
+
+    // Every packet has a verdict, so the rejection arrives on the socket, not from the call
+    assert!(queue.accept_all_pending().is_ok());
+    assert_eq!(handle.process_pending().ok(), Some(1));
+    assert_eq!(errors.get(), 1);
+}
+
+#[test]
+fn queue_fn() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue_fn(0, |hq, message| {
+        if let Ok(m) = message {
+            let _ = PendingVerdict::new(hq, m.header.id()).accept();
+        }
+        0
+    });
+    assert!(queue.is_ok());
+}
+
+#[test]
+fn queue_fn_without_status() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue_fn(0, |hq, message| {
+        if let Ok(m) = message {
+            let _ = hq.accept(m.header.id());
+        }
+    });
+    assert!(queue.is_ok());
+}
+
+#[test]
+fn handler_results() {
+    assert_eq!(().into_status(), 0);
+    assert_eq!(true.into_status(), 0);
+    assert_eq!(false.into_status(), -1);
+    assert_eq!((-2).into_status(), -2);
+}
+
+#[test]
+fn parse_error_policy() {
+    assert_eq!(ParseErrorPolicy::default(), ParseErrorPolicy::SkipAndContinue);
+
+    let mut handle = Handle::new().ok().unwrap();
+    let mut queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    queue.set_parse_error_policy(ParseErrorPolicy::SkipAndCount);
+}
+
+#[test]
+fn toggle_copy_mode() {
+    let mut handle = Handle::new().ok().unwrap();
+    let mut queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    assert!(queue.set_mode(CopyMode::Metadata).is_ok());
+    assert!(queue.set_mode(CopyMode::Packet(0xffff)).is_ok());
+
+    let qh = queue.handle();
+    assert!(qh.set_mode(CopyMode::Metadata).is_ok());
+    assert!(qh.set_mode(CopyMode::Packet(64)).is_ok());
+}
+
+struct Failing;
+
+impl PacketHandler for Failing {
+    fn handle(&mut self, _: QueueHandle, _: Result<&Message, &Error>) -> i32 { -1 }
+}
+
+#[test]
+fn fail_open() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let message = Message::from_bytes(1, &[]);
+
+    assert_eq!(FailOpen(Failing).handle(queue.handle(), Ok(&message)), -1);
+    assert_eq!(queue.stats().accepted, 1);
+}
+
+struct Panicking;
+
+impl PacketHandler for Panicking {
+    fn handle(&mut self, _: QueueHandle, _: Result<&Message, &Error>) -> i32 { panic!("handler failed") }
+}
+
+#[test]
+fn fail_closed_on_panic() {
+    let mut handle = Handle::new().ok().unwrap();
+    let queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    let message = Message::from_bytes(1, &[]);
+    let mut handler = FailClosed(Panicking);
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| handler.handle(queue.handle(), Ok(&message))));
+    assert!(res.is_err());
+    assert_eq!(queue.stats().dropped, 1);
+}
+
+#[test]
+fn copy_mode() {
+    let mut handle = Handle::new().ok().unwrap();
+    let mut queue = handle.queue(0, |_: &Message| Verdict::Accept).ok().unwrap();
+    assert_eq!(queue.copy_mode(), CopyMode::None);
+
+    queue.set_mode(CopyMode::Packet(128)).ok().unwrap();
+    assert_eq!(queue.copy_mode(), CopyMode::Packet(128));
+
+    queue.handle().set_mode(CopyMode::Metadata).ok().unwrap();
+    assert_eq!(queue.copy_mode(), CopyMode::Metadata);
+}
+
+#[test]
+fn event_without_payload() {
+    let mut handle = Handle::new().ok().unwrap();
+    let _queue = handle.event_queue(EVENT_QUEUE).ok().unwrap();
+    send_to_handle(&handle, &metadata_message(EVENT_QUEUE));
+
+    // The packet can still be released
+    let event = handle.packets().next().unwrap().ok().unwrap();
+    assert_eq!(event.id, 1);
+    assert!(event.payload.is_err());
+    assert!(event.verdict.set(Verdict::Accept).is_ok());
+}
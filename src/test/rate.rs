@@ -0,0 +1,32 @@
+use std::time::{Duration, Instant};
+use super::super::rate::RateLimiter;
+
+#[test]
+fn burst() {
+    let mut limiter = RateLimiter::new(1.0, 3);
+    let now = Instant::now();
+    assert!(limiter.allow_at(now));
+    assert!(limiter.allow_at(now));
+    assert!(limiter.allow_at(now));
+    assert!(!limiter.allow_at(now));
+}
+
+#[test]
+fn steady_state() {
+    let mut limiter = RateLimiter::new(10.0, 1);
+    let start = Instant::now();
+    assert!(limiter.allow_at(start));
+
+    // One token is added every 100ms, so only every other 50ms tick is allowed
+    let allowed = (1..21).filter(|&i| limiter.allow_at(start + Duration::from_millis(i * 50))).count();
+    assert_eq!(allowed, 10);
+}
+
+#[test]
+fn refill_capped_at_burst() {
+    let mut limiter = RateLimiter::new(100.0, 2);
+    let later = Instant::now() + Duration::from_secs(10);
+    assert!(limiter.allow_at(later));
+    assert!(limiter.allow_at(later));
+    assert!(!limiter.allow_at(later));
+}
@@ -0,0 +1,39 @@
+use super::super::queue::{parse_kernel_stats, KernelQueueStats};
+
+const CONTENTS: &str = "    0  12345     2 2 65535     7     3     1024  1
+    1  12346     0 1     0     0     0        5  1
+";
+
+#[test]
+fn kernel_stats() {
+    assert_eq!(parse_kernel_stats(CONTENTS, 0), Some(KernelQueueStats {
+        queue_num: 0,
+        peer_portid: 12345,
+        queue_total: 2,
+        copy_mode: 2,
+        copy_range: 65535,
+        queue_dropped: 7,
+        user_dropped: 3,
+        id_sequence: 1024
+    }));
+    assert_eq!(parse_kernel_stats(CONTENTS, 1).map(|s| s.id_sequence), Some(5));
+}
+
+#[test]
+fn kernel_stats_missing_queue() {
+    assert_eq!(parse_kernel_stats(CONTENTS, 2), None);
+    assert_eq!(parse_kernel_stats("", 0), None);
+}
+
+#[test]
+fn user_dropped_since() {
+    let prev = parse_kernel_stats(CONTENTS, 0).unwrap();
+    let mut now = prev;
+    now.user_dropped = 10;
+    assert_eq!(now.user_dropped_since(&prev), 7);
+
+    now.user_dropped = 1;
+    let mut wrapped = prev;
+    wrapped.user_dropped = 0xffff_fffe;
+    assert_eq!(now.user_dropped_since(&wrapped), 3);
+}
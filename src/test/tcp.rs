@@ -0,0 +1,86 @@
+use super::super::message::{clamp_mss, IPHeader, TcpHeader};
+use super::super::message::checksum::tcp_checksum;
+
+// A SYN with MSS 1460, NOP, window scale 7, and EOL padding, in a 32-byte header
+const SYN: [u8; 32] = [0x9c, 0x40, 0x00, 0x50, 0x12, 0x34, 0x56, 0x78, 0x00, 0x00,
+                       0x00, 0x00, 0x80, 0x02, 0xfa, 0xf0, 0x00, 0x00, 0x00, 0x00,
+                       0x02, 0x04, 0x05, 0xb4, 0x01, 0x03, 0x03, 0x07, 0x00, 0x00,
+                       0x00, 0x00];
+
+fn header(segment: &[u8]) -> &TcpHeader {
+    unsafe { &*(segment.as_ptr() as *const TcpHeader) }
+}
+
+#[test]
+fn options() {
+    let options: Vec<(u8, &[u8])> = header(&SYN).options(&SYN).collect();
+    assert_eq!(options, vec![(2, &[0x05, 0xb4][..]), (1, &[][..]), (3, &[0x07][..])]);
+}
+
+#[test]
+fn options_truncated() {
+    // The window scale option is cut short
+    let options: Vec<(u8, &[u8])> = header(&SYN).options(&SYN[..27]).collect();
+    assert_eq!(options, vec![(2, &[0x05, 0xb4][..]), (1, &[][..])]);
+}
+
+#[test]
+fn options_malformed_length() {
+    let mut segment = SYN;
+    segment[21] = 0;
+    assert_eq!(header(&segment).options(&segment).count(), 0);
+}
+
+// 192.168.0.1 -> 192.168.0.199
+fn ip_header() -> IPHeader {
+    IPHeader {
+        version_and_header_raw: 0x45,
+        dscp_raw: 0,
+        total_length_raw: 0,
+        id_raw: 0,
+        flags_and_offset_raw: 0,
+        ttl_raw: 64,
+        protocol_raw: 6,
+        checksum_raw: 0,
+        saddr_raw: u32::to_be(0xc0a80001),
+        daddr_raw: u32::to_be(0xc0a800c7)
+    }
+}
+
+fn with_checksum(mut segment: [u8; 32]) -> [u8; 32] {
+    let checksum = tcp_checksum(&ip_header(), &segment);
+    segment[16] = (checksum >> 8) as u8;
+    segment[17] = checksum as u8;
+    segment
+}
+
+fn assert_checksum(segment: &[u8]) {
+    let checksum = tcp_checksum(&ip_header(), segment);
+    assert_eq!(&segment[16..18], &[(checksum >> 8) as u8, checksum as u8][..]);
+}
+
+#[test]
+fn clamp() {
+    let mut segment = with_checksum(SYN);
+    assert!(clamp_mss(&mut segment, 1400));
+    assert_eq!(&segment[22..24], &[0x05, 0x78][..]);
+    assert_checksum(&segment);
+}
+
+#[test]
+fn clamp_below_max() {
+    let mut segment = with_checksum(SYN);
+    assert!(!clamp_mss(&mut segment, 1500));
+    assert_eq!(segment, with_checksum(SYN));
+}
+
+#[test]
+fn clamp_misaligned() {
+    // A NOP before the MSS option puts its value at an odd offset
+    let mut segment = SYN;
+    segment[20..28].copy_from_slice(&[0x01, 0x02, 0x04, 0x05, 0xb4, 0x03, 0x03, 0x07]);
+    let mut segment = with_checksum(segment);
+    assert!(clamp_mss(&mut segment, 1200));
+    assert_eq!(&segment[23..25], &[0x04, 0xb0][..]);
+    assert_checksum(&segment);
+}
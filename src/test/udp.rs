@@ -0,0 +1,13 @@
+use super::super::message::UdpHeader;
+
+// A DNS query from port 53000 to port 53, 36 bytes long
+const DATAGRAM: [u8; 8] = [0xcf, 0x08, 0x00, 0x35, 0x00, 0x24, 0x1c, 0x7a];
+
+#[test]
+fn fields() {
+    let header = unsafe { &*(DATAGRAM.as_ptr() as *const UdpHeader) };
+    assert_eq!(header.sport(), 53000);
+    assert_eq!(header.dport(), 53);
+    assert_eq!(header.length(), 36);
+    assert_eq!(header.checksum(), 0x1c7a);
+}
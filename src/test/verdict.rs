@@ -0,0 +1,17 @@
+use super::super::queue::Verdict;
+
+#[test]
+fn values() {
+    assert_eq!(Verdict::Drop.as_u32(), 0);
+    assert_eq!(Verdict::Accept.as_u32(), 1);
+    assert_eq!(Verdict::Stolen.as_u32(), 2);
+    assert_eq!(Verdict::Queue(0).as_u32(), 3);
+    assert_eq!(Verdict::Repeat.as_u32(), 4);
+    assert_eq!(Verdict::Stop.as_u32(), 5);
+}
+
+#[test]
+fn queue_number() {
+    assert_eq!(Verdict::Queue(1).as_u32(), 0x0001_0003);
+    assert_eq!(Verdict::Queue(0xffff).as_u32(), 0xffff_0003);
+}
@@ -0,0 +1,36 @@
+use super::super::message::{find_attribute, VlanTag};
+
+// The nested attributes of `NFQA_VLAN`, whose lengths and types are in host order
+fn vlan_attributes() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&6u16.to_ne_bytes());
+    bytes.extend_from_slice(&1u16.to_ne_bytes());
+    bytes.extend_from_slice(&[0x81, 0x00, 0, 0]);
+    bytes.extend_from_slice(&6u16.to_ne_bytes());
+    bytes.extend_from_slice(&2u16.to_ne_bytes());
+    bytes.extend_from_slice(&[0xb0, 0x64, 0, 0]);
+    bytes
+}
+
+#[test]
+fn find() {
+    let bytes = vlan_attributes();
+    assert_eq!(find_attribute(&bytes, 1), Some(&[0x81, 0x00][..]));
+    assert_eq!(find_attribute(&bytes, 2), Some(&[0xb0, 0x64][..]));
+    assert_eq!(find_attribute(&bytes, 3), None);
+}
+
+#[test]
+fn find_truncated() {
+    let bytes = vlan_attributes();
+    assert_eq!(find_attribute(&bytes[..10], 2), None);
+}
+
+#[test]
+fn tag() {
+    let tag = VlanTag::parse(&vlan_attributes()).unwrap();
+    assert_eq!(tag.tpid(), 0x8100);
+    assert_eq!(tag.vid(), 100);
+    assert_eq!(tag.pcp(), 5);
+    assert!(tag.dei());
+}
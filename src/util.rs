@@ -2,7 +2,7 @@
 // Copypasta from <https://github.com/rust-lang/rust/blob/f46c4e158d395cf6e186bf6afdf1705c12071cbe/src/libcore/ptr.rs#L370-376>
 
 #[inline]
-pub unsafe fn as_mut<'a, T: ?Sized>(ptr: &*mut T) -> Option<&'a mut T> where T: Sized {
+pub unsafe fn as_mut<'a, T>(ptr: &*mut T) -> Option<&'a mut T> {
     if ptr.is_null() {
         None
     } else {
@@ -11,7 +11,7 @@ pub unsafe fn as_mut<'a, T: ?Sized>(ptr: &*mut T) -> Option<&'a mut T> where T:
 }
 
 #[inline]
-pub unsafe fn as_ref<'a, T: ?Sized>(ptr: &*const T) -> Option<&'a T> where T: Sized {
+pub unsafe fn as_ref<'a, T>(ptr: &*const T) -> Option<&'a T> {
     if ptr.is_null() {
         None
     } else {